@@ -1,9 +1,24 @@
 use crate::config::Config;
+use crate::goal::{Goal, Progress};
+use crate::history::{History, PhaseOutcome, PhaseRecord};
+use crate::hooks::{Hook, Hooks, Invocation, Trigger};
+use crate::session::{SessionFile, Snapshot};
+use crate::stats;
+use crate::tasks::TaskList;
+use crate::theme::Theme;
+
+use chrono::{Local, NaiveDate};
 
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
+mod clock;
+mod phase;
+#[cfg(test)]
+mod tests;
 mod timer;
+use self::clock::{Clock, SystemClock};
+use self::phase::{Cycle, Phase};
 use self::timer::{State, Timer};
 
 // 25 mins
@@ -13,64 +28,469 @@ const DEFAULT_SHORT_BREAK_TIME: u64 = 60 * 5;
 // 20 mins
 const DEFAULT_LONG_BREAK_TIME: u64 = 60 * 20;
 
-const DEFAULT_POMODOROS_BEFORE_LONG_BREAK: u64 = 4;
+/// Less time asleep than this always counts, it isn't worth pausing or ending a state for
+const MIN_SUSPEND: Duration = Duration::from_secs(2);
 
 pub struct App {
     state: AppState,
+    /// Index of the current phase in the cycle
+    position: u64,
+    clock: Arc<dyn Clock>,
     timer: Timer,
     progress: f64,
     time_left: (u64, u64),
     time_elapsed: (u64, u64),
+    /// Minutes and seconds past the end of the work session, while in overtime
+    overtime: Option<(u64, u64)>,
     pomodoros: u64,
+    /// Length (in seconds) of each work session that counts towards `pomodoros`, these can differ
+    /// when the profile is changed
+    completed_work: Vec<u64>,
+    /// Overtime (in seconds) of each work session in `completed_work`
+    completed_overtime: Vec<u64>,
+    config: Arc<Config>,
     settings: AppSettings,
+    /// When the current phase started (excluding any excess carried over from the previous one)
+    phase_start: SystemTime,
+    history: Option<History>,
+    session: Option<SessionFile>,
+    /// What the time is being spent on
+    label: Option<String>,
+    tasks: Option<TaskList>,
+    goal: Option<Goal>,
+    /// Work finished today (not counting the current phase) and which day that is
+    today: (NaiveDate, Progress),
+    /// Whether the goal has been reached today
+    goal_reached: bool,
+    /// Whether `on_time_left` has been run for the current state
+    warned: bool,
+    /// Whether `on_overtime` has been run for the current work session
+    announced_overtime: bool,
+    /// Whether the state is waiting to be started, it hasn't been paused
+    held: bool,
+    /// Hooks that have been triggered but not run yet
+    invocations: Vec<Invocation>,
+    /// Every hook that has been triggered since `take_triggers`, whether it has a script or not
+    triggers: Vec<Trigger>,
+    /// Time the system had been suspended for at the last update
+    suspended: Duration,
 }
 
 struct AppSettings {
+    profile: String,
     work_time: u64,
     short_break_time: u64,
     long_break_time: u64,
-    pomodoros_before_long_break: u64,
+    /// The phases the timer goes around
+    cycle: Cycle,
+    hooks: Hooks,
+    /// Seconds before the end of a state that `on_time_left` is run
+    time_left_warning: Option<u64>,
+    theme: Theme,
+    /// Whether a finished work session keeps going until it is ended, rather than the break
+    /// starting straight away
+    overtime: bool,
+    /// Whether a work session or break that the timer moves on to starts running straight away,
+    /// otherwise it waits (paused) to be started
+    auto_start_work: bool,
+    auto_start_break: bool,
+    /// Whether a state that waits to be started keeps the time since the last state ended
+    carry_excess: bool,
+    on_suspend: Suspend,
 }
 
-#[derive(PartialEq)]
-pub enum AppState {
-    ShortBreak,
-    LongBreak(bool),
-    Work,
+/// What happens to a running timer when the system is suspended
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Suspend {
+    /// The time asleep counts towards the state, as if the timer had kept running
+    Count,
+    /// The timer is paused when the system wakes up, so the time asleep doesn't count
+    Pause,
+    /// The state ends when the system wakes up
+    End,
 }
 
-impl App {
-    pub fn new(config: Arc<Config>) -> App {
-        let settings = AppSettings {
-            work_time: config
-                .get_int("work_time")
-                .map(|x| x as u64)
-                .unwrap_or(DEFAULT_WORK_TIME),
+impl AppSettings {
+    fn from_config(config: &Config) -> AppSettings {
+        AppSettings {
+            profile: config.active_profile().to_string(),
+            work_time: config.get_int("work_time").unwrap_or(DEFAULT_WORK_TIME),
             short_break_time: config
                 .get_int("short_break_time")
-                .map(|x| x as u64)
                 .unwrap_or(DEFAULT_SHORT_BREAK_TIME),
             long_break_time: config
                 .get_int("long_break_time")
-                .map(|x| x as u64)
                 .unwrap_or(DEFAULT_LONG_BREAK_TIME),
-            pomodoros_before_long_break: config
-                .get_int("pomodoros_before_long_break")
-                .map(|x| x as u64)
-                .unwrap_or(DEFAULT_POMODOROS_BEFORE_LONG_BREAK),
+            cycle: Cycle::from_config(config),
+            hooks: Hooks::from_config(config),
+            time_left_warning: config.get_int("time_left_warning"),
+            theme: Theme::from_config(config),
+            overtime: config.get_bool("overtime").unwrap_or(false),
+            auto_start_work: config.get_bool("auto_start_work").unwrap_or(true),
+            auto_start_break: config.get_bool("auto_start_break").unwrap_or(true),
+            carry_excess: config.get_string("start_excess").map(String::as_str) == Some("carry"),
+            on_suspend: match config.get_string("suspend").map(String::as_str) {
+                Some("pause") => Suspend::Pause,
+                Some("end") => Suspend::End,
+                _ => Suspend::Count,
+            },
+        }
+    }
+
+    /// Length in seconds of the phase at `position` when it is started as `state`, an elongated
+    /// break is always as long as a long break
+    fn phase_time(&self, position: u64, state: AppState) -> u64 {
+        let time = match state {
+            AppState::LongBreak(true) => return self.long_break_time,
+            AppState::LongBreak(false) => self.long_break_time,
+            AppState::ShortBreak => self.short_break_time,
+            AppState::Work => self.work_time,
         };
 
-        App {
-            state: AppState::Work,
-            timer: Timer::new(Duration::from_secs(settings.work_time), false),
+        self.cycle.get(position).time.unwrap_or(time)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AppState {
+    ShortBreak,
+    LongBreak(bool),
+    Work,
+}
+
+impl AppState {
+    /// Short name used when storing the state in files
+    pub fn key(&self) -> &'static str {
+        match self {
+            AppState::Work => "work",
+            AppState::ShortBreak => "short_break",
+            AppState::LongBreak(false) => "long_break",
+            AppState::LongBreak(true) => "long_break_elongated",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<AppState> {
+        match key {
+            "work" => Some(AppState::Work),
+            "short_break" => Some(AppState::ShortBreak),
+            "long_break" => Some(AppState::LongBreak(false)),
+            "long_break_elongated" => Some(AppState::LongBreak(true)),
+            _ => None,
+        }
+    }
+}
+
+impl App {
+    pub fn new(config: Arc<Config>) -> App {
+        App::with_clock(config, Arc::new(SystemClock::new()))
+    }
+
+    /// Like `new` but the timers read the time from `clock`
+    pub fn with_clock(config: Arc<Config>, clock: Arc<dyn Clock>) -> App {
+        let settings = AppSettings::from_config(&config);
+        let state = settings.cycle.get(0).state;
+
+        let mut app = App {
+            state,
+            position: 0,
+            timer: Timer::new(
+                Arc::clone(&clock),
+                Duration::from_secs(settings.phase_time(0, state)),
+                false,
+            ),
+            suspended: clock.suspended(),
+            clock,
             progress: 0.0,
             time_left: (0, 0),
             time_elapsed: (0, 0),
+            overtime: None,
             pomodoros: 0,
+            completed_work: Vec::new(),
+            completed_overtime: Vec::new(),
             settings,
+            phase_start: SystemTime::now(),
+            history: History::from_config(&config),
+            session: SessionFile::from_config(&config),
+            label: None,
+            tasks: None,
+            goal: Goal::from_config(&config),
+            today: (Local::now().date_naive(), Progress::default()),
+            goal_reached: false,
+            warned: false,
+            announced_overtime: false,
+            held: false,
+            invocations: Vec::new(),
+            triggers: Vec::new(),
+            config,
+        };
+
+        // Don't run `on_goal_reached` for a goal that was already reached in an earlier session
+        app.refresh_today();
+        app.invocations.clear();
+        app.triggers.clear();
+
+        app
+    }
+
+    /// Rebuilds the app from a snapshot, any time that passed since the snapshot was taken counts
+    /// towards the timer (unless it was paused)
+    pub fn resume(config: Arc<Config>, snapshot: &Snapshot) -> App {
+        let mut app = App::new(config);
+
+        // The profile may have been removed from the config since, if so stick with the default
+        let _ = app.switch_profile(&snapshot.profile);
+        app.state = snapshot.state;
+        app.pomodoros = snapshot.pomodoros;
+        app.position =
+            app.settings
+                .cycle
+                .locate(&snapshot.phase, snapshot.state, snapshot.pomodoros);
+        // Without the work sessions in the snapshot each pomodoro counts as one of `work_time`
+        app.completed_work = snapshot
+            .completed_work
+            .clone()
+            .unwrap_or_else(|| vec![app.settings.work_time; snapshot.pomodoros as usize]);
+        app.completed_overtime = snapshot.completed_overtime.clone();
+        app.completed_overtime.resize(app.completed_work.len(), 0);
+        app.phase_start = snapshot.phase_start;
+        app.label = snapshot.label.clone();
+        app.held = snapshot.held;
+        app.timer = Timer::new_with_acc_duration(
+            Arc::clone(&app.clock),
+            Duration::from_secs(app.settings.phase_time(app.position, snapshot.state)),
+            snapshot.paused,
+            snapshot.elapsed_now(),
+        );
+
+        // `on_time_left` and `on_overtime` may have already been run before the snapshot
+        app.check_time_left();
+        app.announced_overtime = app.is_overtime();
+        app.invocations.clear();
+        app.triggers.clear();
+
+        app
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            state: self.state,
+            phase: self.phase().name.clone(),
+            pomodoros: self.pomodoros,
+            completed_work: Some(self.completed_work.clone()),
+            completed_overtime: self.completed_overtime.clone(),
+            profile: self.settings.profile.clone(),
+            elapsed: self.timer.total_duration(),
+            paused: self.is_paused(),
+            held: self.held,
+            phase_start: self.phase_start,
+            label: self.label.clone(),
+            saved_at: SystemTime::now(),
         }
     }
 
+    /// Saves a snapshot to the session file so that the session can be resumed later
+    pub fn save_snapshot(&self) {
+        if let Some(session) = &self.session {
+            // Like the history this is best-effort
+            let _ = session.save(&self.snapshot());
+        }
+    }
+
+    /// Uses the settings from the profile for every state after the current one, carrying on
+    /// from the same place in the profile's cycle
+    pub fn switch_profile(&mut self, name: &str) -> Result<(), String> {
+        let config = self.config.with_profile(name)?;
+        let phase = self.phase().name.clone();
+
+        self.settings = AppSettings::from_config(&config);
+        self.position = self
+            .settings
+            .cycle
+            .locate(&phase, self.state, self.pomodoros);
+
+        Ok(())
+    }
+
+    /// Switches to the profile after the current one (in the order they are in the config)
+    pub fn next_profile(&mut self) {
+        let names = self.config.profile_names();
+        let current = names
+            .iter()
+            .position(|name| *name == self.settings.profile)
+            .unwrap_or(0);
+        let next = names[(current + 1) % names.len()].to_string();
+
+        self.switch_profile(&next)
+            .expect("profile names come from the config");
+    }
+
+    pub fn profile(&self) -> &str {
+        &self.settings.profile
+    }
+
+    /// Whether the config has any profiles other than the default one
+    pub fn has_profiles(&self) -> bool {
+        self.config.profile_names().len() > 1
+    }
+
+    /// Changes the length of a state (until the profile is switched), the current state is
+    /// changed too if it is the same kind. Phases with their own `time` keep it.
+    pub fn set_state_time(&mut self, state: AppState, seconds: u64) {
+        match state {
+            AppState::LongBreak(_) => self.settings.long_break_time = seconds,
+            AppState::ShortBreak => self.settings.short_break_time = seconds,
+            AppState::Work => self.settings.work_time = seconds,
+        }
+
+        if std::mem::discriminant(&state) == std::mem::discriminant(&self.state) {
+            let time = self.settings.phase_time(self.position, self.state);
+            self.timer.set_target_duration(Duration::from_secs(time));
+            self.save_snapshot();
+        }
+    }
+
+    /// The phase of the cycle that the timer is in
+    pub fn phase(&self) -> &Phase {
+        self.settings.cycle.get(self.position)
+    }
+
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// Sets what the time is being spent on, the label is kept on a single line so it can be
+    /// written to the history (an empty label clears it)
+    pub fn set_label(&mut self, label: Option<String>) {
+        self.label = label
+            .map(|label| label.replace(char::is_control, " ").trim().to_string())
+            .filter(|label| !label.is_empty());
+
+        self.save_snapshot();
+    }
+
+    pub fn tasks(&self) -> Option<&TaskList> {
+        self.tasks.as_ref()
+    }
+
+    pub fn tasks_mut(&mut self) -> Option<&mut TaskList> {
+        self.tasks.as_mut()
+    }
+
+    pub fn set_tasks(&mut self, tasks: Option<TaskList>) {
+        self.tasks = tasks;
+    }
+
+    /// The daily goal and today's progress towards it, including the running work session
+    pub fn goal_progress(&self) -> Option<(Goal, Progress)> {
+        let goal = self.goal?;
+        let mut progress = self.today.1;
+
+        if self.state == AppState::Work {
+            progress.work_time += self.timer.elapsed().min(self.timer.target_duration());
+        }
+
+        Some((goal, progress))
+    }
+
+    /// Counts the work done today from the history, so it carries over when the app is restarted
+    /// (without a history only this session counts)
+    fn refresh_today(&mut self) {
+        let goal = match self.goal {
+            Some(goal) => goal,
+            None => return,
+        };
+
+        let date = Local::now().date_naive();
+        let progress = match self.history.as_ref().map(|history| history.read_records()) {
+            Some(Ok(records)) => stats::progress_on(&records, date),
+            _ => Progress {
+                pomodoros: self.pomodoros,
+                work_time: Duration::from_secs(self.completed_work.iter().sum()),
+            },
+        };
+
+        let reached = goal.is_reached(&progress);
+        self.today = (date, progress);
+
+        if reached && !self.goal_reached {
+            self.fire(Hook::GoalReached, Vec::new());
+        }
+
+        self.goal_reached = reached;
+    }
+
+    /// Queues the script for the hook (if there is one) with the current state of the app, `env`
+    /// is added to the variables every hook gets
+    pub fn fire(&mut self, hook: Hook, mut env: Vec<(&'static str, String)>) {
+        let duration = self.timer.target_duration().as_secs();
+        let elapsed = self.timer.elapsed().as_secs().min(duration);
+
+        let mut variables = vec![
+            ("TOMATO_EVENT", hook.name().to_string()),
+            ("TOMATO_STATE", self.state.key().to_string()),
+            ("TOMATO_PHASE", self.phase().name.clone()),
+            ("TOMATO_POMODOROS", self.pomodoros.to_string()),
+            ("TOMATO_DURATION", duration.to_string()),
+            ("TOMATO_ELAPSED", elapsed.to_string()),
+            ("TOMATO_REMAINING", (duration - elapsed).to_string()),
+            ("TOMATO_PROFILE", self.settings.profile.clone()),
+        ];
+
+        if let Some(label) = &self.label {
+            variables.push(("TOMATO_TASK", label.clone()));
+            // From before the variables had the prefix
+            variables.push(("TASK", label.clone()));
+        }
+
+        variables.append(&mut env);
+
+        // The phase's own scripts take the place of the ones for its state
+        let phase = self.phase();
+        let script = match hook {
+            Hook::WorkStart | Hook::BreakStart => phase.on_start.as_deref(),
+            Hook::WorkEnd | Hook::BreakEnd => phase.on_end.as_deref(),
+            _ => None,
+        }
+        .map(|script| (script, self.settings.hooks.timeout(hook)))
+        .or_else(|| self.settings.hooks.get(hook));
+
+        if let Some((script, timeout)) = script {
+            self.invocations.push(Invocation {
+                hook,
+                script: script.to_string(),
+                env: variables.clone(),
+                timeout,
+            });
+        }
+
+        self.triggers.push(Trigger {
+            hook,
+            env: variables,
+        });
+    }
+
+    /// The hooks that have been triggered since this was last called, in order
+    pub fn take_invocations(&mut self) -> Vec<Invocation> {
+        std::mem::take(&mut self.invocations)
+    }
+
+    /// The hooks that have been triggered since this was last called, for things other than the
+    /// scripts that react to them (like notifications)
+    pub fn take_triggers(&mut self) -> Vec<Trigger> {
+        std::mem::take(&mut self.triggers)
+    }
+
+    /// The theme, with the current phase's colour (if it has one) as the foreground
+    pub fn theme(&self) -> Theme {
+        let mut theme = self.settings.theme;
+
+        if let Some(colour) = self.phase().colour {
+            theme.foreground = colour;
+        }
+
+        theme
+    }
+
     pub fn progress(&self) -> f64 {
         self.progress
     }
@@ -89,6 +509,28 @@ impl App {
         self.time_elapsed
     }
 
+    /// Returns minutes and seconds past the end of the work session, if it has gone into
+    /// overtime
+    pub fn overtime(&self) -> Option<(u64, u64)> {
+        self.overtime
+    }
+
+    /// Whether the work session has finished and is waiting to be ended (with `overtime` on)
+    pub fn is_overtime(&self) -> bool {
+        self.current_overtime().is_some()
+    }
+
+    fn current_overtime(&self) -> Option<Duration> {
+        if self.state != AppState::Work || !self.settings.overtime {
+            return None;
+        }
+
+        match self.timer.get_state() {
+            State::Finished(excess) => Some(excess),
+            State::Paused | State::Running => None,
+        }
+    }
+
     pub fn pomodoros(&self) -> u64 {
         self.pomodoros
     }
@@ -99,7 +541,7 @@ impl App {
     /// skipped through a work session it will still count as the total time (this is the intended
     /// behaviour).
     pub fn total_work_time(&self) -> (u64, u64) {
-        let historic_minutes = (self.pomodoros() * self.settings.work_time) / 60;
+        let historic_minutes = self.completed_work.iter().sum::<u64>() / 60;
         let (running_minutes, running_seconds) = if self.get_state() == &AppState::Work {
             self.time_elapsed()
         } else {
@@ -113,60 +555,93 @@ impl App {
         (hours, minutes)
     }
 
-    pub fn get_state_name(&self) -> &'static str {
+    /// Returns `(hours, minutes)` of overtime, including the running work session. This is kept
+    /// apart from `total_work_time`.
+    pub fn total_overtime(&self) -> (u64, u64) {
+        let seconds = self.completed_overtime.iter().sum::<u64>()
+            + self
+                .current_overtime()
+                .map_or(0, |overtime| overtime.as_secs());
+        let total_minutes = seconds / 60;
+
+        (total_minutes / 60, total_minutes % 60)
+    }
+
+    /// The title of the current phase
+    pub fn get_state_name(&self) -> String {
         match self.state {
-            AppState::LongBreak(elongated) => {
-                if elongated {
-                    "Long Break (elongated)"
-                } else {
-                    "Long Break"
-                }
-            }
-            AppState::ShortBreak => "Short Break",
-            AppState::Work => "Work",
+            AppState::LongBreak(true) => "Long Break (elongated)".to_string(),
+            _ => self.phase().title(),
         }
     }
 
-    pub fn transition_to_next_state(&mut self, last_finished: Duration) {
-        let next_state = match self.state {
-            AppState::LongBreak(_) | AppState::ShortBreak => AppState::Work,
-            AppState::Work => {
-                self.pomodoros += 1;
-
-                if self.pomodoros % self.settings.pomodoros_before_long_break == 0 {
-                    AppState::LongBreak(false)
-                } else {
-                    AppState::ShortBreak
+    /// Moves on to the next phase in the cycle
+    pub fn transition_to_next_state(&mut self, last_finished: Duration, outcome: PhaseOutcome) {
+        let next = self.settings.cycle.next(self.position);
+
+        if self.state == AppState::Work {
+            self.pomodoros += 1;
+            self.completed_work
+                .push(self.timer.target_duration().as_secs());
+            self.completed_overtime.push(
+                self.current_overtime()
+                    .map_or(0, |overtime| overtime.as_secs()),
+            );
+
+            if outcome == PhaseOutcome::Completed {
+                if let (Some(tasks), Some(label)) = (&mut self.tasks, &self.label) {
+                    // Like the history this is best-effort
+                    let _ = tasks.add_pomodoro(label);
                 }
             }
-        };
+        }
 
-        self.transition_to_state(next_state, last_finished);
+        let next_state = self.settings.cycle.get(next).state;
+        self.transition_to_state(next, next_state, last_finished, outcome);
     }
 
+    /// Moves back to the previous phase in the cycle, taking back the pomodoro if that is a work
+    /// session
     pub fn transition_to_prev_state(&mut self, last_finished: Duration) {
-        let next_state = if self.pomodoros == 0 {
-            // Don't keep subtracting pomodoros, there are no more states to transition between
-            AppState::Work
-        } else {
-            match self.state {
-                AppState::LongBreak(_) | AppState::ShortBreak => {
-                    self.pomodoros -= 1;
-                    AppState::Work
-                }
-                AppState::Work => {
-                    let state = if self.pomodoros % self.settings.pomodoros_before_long_break == 0 {
-                        AppState::LongBreak(false)
-                    } else {
-                        AppState::ShortBreak
-                    };
+        let prev = self.settings.cycle.prev(self.position);
+        let prev_state = self.settings.cycle.get(prev).state;
 
-                    state
-                }
-            }
+        // There is nothing before the start of the first cycle, so the first phase restarts
+        let prev = if self.pomodoros == 0 && self.position == 0 {
+            0
+        } else {
+            prev
         };
 
-        self.transition_to_state(next_state, last_finished);
+        // Don't keep subtracting pomodoros, there are none to take back
+        let took_back = prev_state == AppState::Work && self.pomodoros > 0;
+
+        if took_back {
+            self.pomodoros -= 1;
+            self.completed_work.pop();
+            self.completed_overtime.pop();
+        }
+
+        let next_state = self.settings.cycle.get(prev).state;
+        self.change_phase(
+            prev,
+            next_state,
+            last_finished,
+            PhaseOutcome::Rewound,
+            Some(took_back),
+        );
+    }
+
+    /// Turns a short break into a long break, does nothing in any other state
+    pub fn elongate_break(&mut self) {
+        if self.state == AppState::ShortBreak {
+            self.transition_to_state(
+                self.position,
+                AppState::LongBreak(true),
+                Duration::new(0, 0),
+                PhaseOutcome::Elongated,
+            );
+        }
     }
 
     /// Resets timer to 0 (same target duration)
@@ -183,24 +658,142 @@ impl App {
         self.timer.rewind_timer(Duration::from_secs(delta_secs));
     }
 
-    pub fn transition_to_state(&mut self, next_state: AppState, elapsed_duration: Duration) {
-        let time = match next_state {
-            AppState::LongBreak(_) => self.settings.long_break_time,
-            AppState::ShortBreak => self.settings.short_break_time,
-            AppState::Work => self.settings.work_time,
-        };
+    /// Ends the current phase (recording it in the history with the given outcome) and starts the
+    /// phase at `position` in the cycle as `next_state` with `elapsed_duration` already on the clock
+    pub fn transition_to_state(
+        &mut self,
+        position: u64,
+        next_state: AppState,
+        elapsed_duration: Duration,
+        outcome: PhaseOutcome,
+    ) {
+        self.change_phase(position, next_state, elapsed_duration, outcome, None);
+    }
 
-        self.timer =
-            Timer::new_with_acc_duration(Duration::from_secs(time), false, elapsed_duration);
+    /// `transition_to_state`, `took_back` is recorded in the history for rewinds
+    fn change_phase(
+        &mut self,
+        position: u64,
+        next_state: AppState,
+        elapsed_duration: Duration,
+        outcome: PhaseOutcome,
+        took_back: Option<bool>,
+    ) {
+        let prev_state = self.state;
+        let prev_phase = self.phase().name.clone();
+        let next = vec![
+            ("TOMATO_NEXT_STATE", next_state.key().to_string()),
+            (
+                "TOMATO_NEXT_PHASE",
+                self.settings.cycle.get(position).name.clone(),
+            ),
+        ];
+
+        match outcome {
+            PhaseOutcome::Skipped => self.fire(Hook::Skip, next.clone()),
+            PhaseOutcome::Rewound => self.fire(Hook::Rewind, next.clone()),
+            PhaseOutcome::Elongated => self.fire(Hook::Elongate, next.clone()),
+            PhaseOutcome::Completed => {}
+        }
+
+        // A break that was made longer hasn't ended
+        if outcome != PhaseOutcome::Elongated {
+            let mut env = next;
+            env.push(("TOMATO_OUTCOME", outcome.as_str().to_string()));
+
+            match prev_state {
+                AppState::Work => self.fire(Hook::WorkEnd, env),
+                _ => self.fire(Hook::BreakEnd, env),
+            }
+        }
+
+        self.record_phase(elapsed_duration, outcome, took_back);
+
+        let time = self.settings.phase_time(position, next_state);
+
+        self.timer = Timer::new_with_acc_duration(
+            Arc::clone(&self.clock),
+            Duration::from_secs(time),
+            false,
+            elapsed_duration,
+        );
         self.state = next_state;
+        self.position = position;
+        self.phase_start = SystemTime::now() - elapsed_duration;
+        self.warned = false;
+        self.announced_overtime = false;
+        self.held = false;
+
+        if outcome != PhaseOutcome::Elongated {
+            let mut env = vec![
+                ("TOMATO_PREV_STATE", prev_state.key().to_string()),
+                ("TOMATO_PREV_PHASE", prev_phase),
+            ];
+
+            match next_state {
+                AppState::Work => self.fire(Hook::WorkStart, env),
+                AppState::ShortBreak | AppState::LongBreak(_) => {
+                    let break_type = match next_state {
+                        AppState::ShortBreak => "short",
+                        _ => "long",
+                    };
+                    env.push(("BREAK_TYPE", break_type.to_string()));
+                    self.fire(Hook::BreakStart, env);
+                }
+            }
+        }
+
+        self.refresh_today();
+        self.save_snapshot();
+    }
+
+    /// Appends the current phase to the history, `overrun` is the time that has passed since the
+    /// phase actually ended
+    fn record_phase(&self, overrun: Duration, outcome: PhaseOutcome, took_back: Option<bool>) {
+        let history = match &self.history {
+            Some(history) => history,
+            None => return,
+        };
+
+        let planned = self.timer.target_duration();
+        let actual = if outcome == PhaseOutcome::Completed {
+            planned + self.current_overtime().unwrap_or_default()
+        } else {
+            self.timer.elapsed()
+        };
+
+        let record = PhaseRecord {
+            state: self.state,
+            start: self.phase_start,
+            end: SystemTime::now() - overrun,
+            planned,
+            actual,
+            outcome,
+            label: self.label.clone(),
+            phase: Some(self.phase().name.clone()),
+            took_back,
+        };
+
+        // The history is best-effort, failing to write to it shouldn't stop the timer
+        let _ = history.append(&record);
     }
 
+    /// A state that was waiting to be started starts from now (along with any excess it kept),
+    /// without running `on_resume` as it was never paused
     pub fn toggle_pause(&mut self) {
-        if self.is_paused() {
+        if self.held {
+            self.held = false;
+            self.timer.start();
+            self.phase_start = SystemTime::now() - self.timer.total_duration();
+        } else if self.is_paused() {
             self.timer.start();
+            self.fire(Hook::Resume, Vec::new());
         } else {
             self.timer.pause();
+            self.fire(Hook::Pause, Vec::new());
         }
+
+        self.save_snapshot();
     }
 
     pub fn get_state(&self) -> &AppState {
@@ -210,28 +803,133 @@ impl App {
     fn update_progress_data(&mut self) {
         let (progress, time_elapsed, time_left) = self.timer.get_progress_data();
 
-        self.progress = progress;
+        // The overtime is shown on its own
+        self.progress = progress.min(1.0);
+        self.overtime = self.current_overtime().map(|overtime| {
+            let seconds = overtime.as_secs();
+            (seconds / 60, seconds % 60)
+        });
 
-        let seconds_elapsed = time_elapsed.as_secs();
+        let seconds_elapsed = time_elapsed.min(self.timer.target_duration()).as_secs();
         self.time_elapsed = (seconds_elapsed / 60, seconds_elapsed % 60);
 
         let seconds_left = time_left.as_secs();
         self.time_left = (seconds_left / 60, seconds_left % 60);
     }
 
-    pub fn update<F>(&mut self, on_new_state: &F)
-    where
-        F: Fn(&AppState),
-    {
+    /// Runs `on_time_left` once when the time left drops to `time_left_warning` (as long as the
+    /// state is longer than the warning)
+    fn check_time_left(&mut self) {
+        let warning = match self.settings.time_left_warning {
+            Some(warning) => Duration::from_secs(warning),
+            None => return,
+        };
+
+        let target = self.timer.target_duration();
+        let left = target - self.timer.elapsed().min(target);
+
+        if left > warning {
+            // Seeking backwards means it can be needed again
+            self.warned = false;
+        } else if !self.warned && target > warning {
+            self.warned = true;
+            self.fire(Hook::TimeLeft, Vec::new());
+        }
+    }
+
+    pub fn update(&mut self) {
+        self.check_suspend();
+
+        if self.goal.is_some() && Local::now().date_naive() != self.today.0 {
+            self.refresh_today();
+        }
+
         match self.timer.get_state() {
-            State::Paused | State::Running => self.update_progress_data(),
+            State::Paused | State::Running => {
+                // Seeking back from overtime means it is announced again when it runs out
+                self.announced_overtime = false;
+                self.update_progress_data();
+                self.check_time_left();
+            }
+            // The work session carries on until it is ended
+            State::Finished(_) if self.is_overtime() => {
+                if !self.announced_overtime {
+                    self.announced_overtime = true;
+                    self.fire(Hook::Overtime, Vec::new());
+                }
+
+                self.update_progress_data();
+            }
             State::Finished(last_finished) => {
                 // All timing is state based so by using the last_finished & the recursive
                 // calling of update, any lag won't cause issues with the correctness of the timer
-                self.transition_to_next_state(last_finished);
-                on_new_state(self.get_state());
-                self.update(on_new_state);
+                self.transition_to_next_state(last_finished, PhaseOutcome::Completed);
+                self.hold_start();
+                self.update();
+            }
+        }
+    }
+
+    /// Pauses a state that the timer has just moved on to if it should wait to be started
+    fn hold_start(&mut self) {
+        let auto_start = match self.state {
+            AppState::Work => self.settings.auto_start_work,
+            AppState::ShortBreak | AppState::LongBreak(_) => self.settings.auto_start_break,
+        };
+
+        if auto_start {
+            return;
+        }
+
+        if self.settings.carry_excess {
+            self.timer.pause();
+
+            // Whatever is carried over has to leave something to start, otherwise it would be
+            // finished before it ever started
+            let total = self.timer.total_duration();
+            let target = self.timer.target_duration();
+            if total >= target {
+                self.timer
+                    .rewind_timer(total - target + Duration::from_secs(1));
+            }
+        } else {
+            self.timer.reset(true);
+        }
+
+        self.held = true;
+
+        self.save_snapshot();
+    }
+
+    /// The timer doesn't count time the system was suspended for, so whatever `suspend` says is
+    /// done with any time asleep since the last update
+    fn check_suspend(&mut self) {
+        let suspended = self.clock.suspended();
+        let asleep = suspended.saturating_sub(self.suspended);
+        self.suspended = suspended;
+
+        if asleep == Duration::new(0, 0) || self.is_paused() {
+            return;
+        }
+
+        match self.settings.on_suspend {
+            // Nobody paused it so the `pause` hook isn't run (and there's no notification)
+            Suspend::Pause if asleep >= MIN_SUSPEND => {
+                self.timer.pause();
+                self.save_snapshot();
+            }
+            Suspend::End if asleep >= MIN_SUSPEND => {
+                let outcome = if self.timer.elapsed() + asleep >= self.timer.target_duration() {
+                    PhaseOutcome::Completed
+                } else {
+                    PhaseOutcome::Skipped
+                };
+
+                self.transition_to_next_state(Duration::new(0, 0), outcome);
+                self.hold_start();
             }
+            // Any states that finished while asleep are moved through on this update
+            _ => self.timer.forward_timer(asleep),
         }
     }
 }
@@ -0,0 +1,71 @@
+use std::time::{Duration, Instant};
+
+/// Where the timers get the time from
+pub trait Clock: Send + Sync {
+    /// Time since the clock was created, it never goes backwards and doesn't include any time the
+    /// system was suspended for
+    fn now(&self) -> Duration;
+
+    /// Total time the system has been suspended for since the clock was created
+    fn suspended(&self) -> Duration;
+}
+
+/// The monotonic clock, changes to the system time don't affect it
+pub struct SystemClock {
+    start: Instant,
+    /// Time asleep before the clock was created
+    suspended_start: Duration,
+}
+
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        SystemClock {
+            start: Instant::now(),
+            suspended_start: suspended_since_boot(),
+        }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn suspended(&self) -> Duration {
+        suspended_since_boot().saturating_sub(self.suspended_start)
+    }
+}
+
+/// The difference between a clock that keeps counting while the system is suspended and one that
+/// doesn't: `CLOCK_BOOTTIME` and `CLOCK_MONOTONIC` on Linux, `CLOCK_MONOTONIC_RAW` and
+/// `CLOCK_UPTIME_RAW` on macOS
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn suspended_since_boot() -> Duration {
+    #[cfg(target_os = "linux")]
+    let (with_sleep, without_sleep) = (libc::CLOCK_BOOTTIME, libc::CLOCK_MONOTONIC);
+    #[cfg(target_os = "macos")]
+    let (with_sleep, without_sleep) = (libc::CLOCK_MONOTONIC_RAW, libc::CLOCK_UPTIME_RAW);
+
+    let read = |clock| {
+        let mut time = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+
+        // Safe as `time` is a valid timespec to write to
+        if unsafe { libc::clock_gettime(clock, &mut time) } == 0 {
+            Duration::new(time.tv_sec as u64, time.tv_nsec as u32)
+        } else {
+            Duration::new(0, 0)
+        }
+    };
+
+    read(with_sleep).saturating_sub(read(without_sleep))
+}
+
+/// Other systems don't say how long they were asleep for. Guessing from the system time would
+/// mistake it being changed for the system sleeping, so no time asleep is ever reported.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn suspended_since_boot() -> Duration {
+    Duration::new(0, 0)
+}
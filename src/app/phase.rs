@@ -0,0 +1,217 @@
+use super::AppState;
+use crate::config::Config;
+use crate::theme;
+
+use tui::style::Color;
+
+const DEFAULT_POMODOROS_BEFORE_LONG_BREAK: u64 = 4;
+
+/// A work session or break in the cycle that the timer goes around
+#[derive(Clone, Debug, PartialEq)]
+pub struct Phase {
+    /// As it is written in `phases`
+    pub name: String,
+    pub state: AppState,
+    /// Length in seconds, `None` uses the time for the state (`work_time`, ...)
+    pub time: Option<u64>,
+    pub colour: Option<Color>,
+    /// Run instead of `on_work_start` or `on_break_start`
+    pub on_start: Option<String>,
+    /// Run instead of `on_work_end` or `on_break_end`
+    pub on_end: Option<String>,
+}
+
+impl Phase {
+    /// Uses the `phase.NAME.*` settings. The state is `phase.NAME.kind`, which defaults to the
+    /// state with the same key for `work`, `short_break` and `long_break` and to a short break for
+    /// anything else.
+    fn from_config(config: &Config, name: &str) -> Phase {
+        let get = |field| config.get_string(&format!("phase.{}.{}", name, field));
+        let state = |key: &str| match key {
+            "work" => Some(AppState::Work),
+            "short_break" => Some(AppState::ShortBreak),
+            "long_break" => Some(AppState::LongBreak(false)),
+            _ => None,
+        };
+
+        Phase {
+            name: name.to_string(),
+            state: get("kind")
+                .and_then(|kind| state(kind))
+                .or_else(|| state(name))
+                .unwrap_or(AppState::ShortBreak),
+            time: config.get_int(&format!("phase.{}.time", name)),
+            colour: get("colour").and_then(|colour| theme::parse_colour(colour)),
+            on_start: get("on_start").cloned(),
+            on_end: get("on_end").cloned(),
+        }
+    }
+
+    /// `Work`, `Short Break` or `Long Break` for the built in phases, otherwise the name with the
+    /// first letter in upper case (and spaces for underscores)
+    pub fn title(&self) -> String {
+        match self.name.as_str() {
+            "work" => "Work".to_string(),
+            "short_break" => "Short Break".to_string(),
+            "long_break" => "Long Break".to_string(),
+            name => {
+                let name = name.replace('_', " ");
+                let mut chars = name.chars();
+
+                match chars.next() {
+                    Some(first) => first.to_uppercase().chain(chars).collect(),
+                    None => name,
+                }
+            }
+        }
+    }
+}
+
+/// The phases that the timer goes around
+pub struct Cycle {
+    /// The phases in `phases` (there is at least one), or the work session, short break and long
+    /// break of the default cycle
+    phases: Vec<Phase>,
+    /// For the default cycle, the work sessions in it. Each has a short break after it except the
+    /// last, which has a long break. Positions in it are worked out rather than stored as
+    /// `pomodoros_before_long_break` can be as big as it likes.
+    pomodoros: Option<u64>,
+}
+
+impl Cycle {
+    /// Uses `phases` if it is set, otherwise `pomodoros_before_long_break` for the default cycle
+    pub fn from_config(config: &Config) -> Cycle {
+        match config.get_list("phases") {
+            Some(names) if !names.is_empty() => Cycle {
+                phases: names
+                    .iter()
+                    .map(|name| Phase::from_config(config, name))
+                    .collect(),
+                pomodoros: None,
+            },
+            _ => Cycle {
+                phases: ["work", "short_break", "long_break"]
+                    .iter()
+                    .map(|name| Phase::from_config(config, name))
+                    .collect(),
+                // Two positions for each, which have to fit in a `u64`
+                pomodoros: Some(
+                    config
+                        .get_int("pomodoros_before_long_break")
+                        .unwrap_or(DEFAULT_POMODOROS_BEFORE_LONG_BREAK)
+                        .clamp(1, u64::MAX / 2),
+                ),
+            },
+        }
+    }
+
+    /// Number of positions in the cycle
+    pub fn len(&self) -> u64 {
+        match self.pomodoros {
+            Some(pomodoros) => pomodoros * 2,
+            None => self.phases.len() as u64,
+        }
+    }
+
+    /// The phase at `position`, which must be less than `len`
+    pub fn get(&self, position: u64) -> &Phase {
+        let index = match self.pomodoros {
+            Some(_) if position.is_multiple_of(2) => 0,
+            Some(_) if position == self.len() - 1 => 2,
+            Some(_) => 1,
+            None => position as usize,
+        };
+
+        &self.phases[index]
+    }
+
+    pub fn next(&self, position: u64) -> u64 {
+        if position + 1 == self.len() {
+            0
+        } else {
+            position + 1
+        }
+    }
+
+    pub fn prev(&self, position: u64) -> u64 {
+        if position == 0 {
+            self.len() - 1
+        } else {
+            position - 1
+        }
+    }
+
+    /// Where the phase called `name` is, so the timer can carry on around a different cycle (after
+    /// switching profile or resuming a session). When the name comes up more than once (or not at
+    /// all, in which case a phase of the same state is used) the one with the right number of work
+    /// sessions before it is picked.
+    pub fn locate(&self, name: &str, state: AppState, pomodoros: u64) -> u64 {
+        // An elongated break is a long break that took the place of a short one
+        let state = match state {
+            AppState::LongBreak(true) => AppState::ShortBreak,
+            state => state,
+        };
+
+        match self.pomodoros {
+            Some(n) => {
+                let state = match name {
+                    "work" => AppState::Work,
+                    "short_break" => AppState::ShortBreak,
+                    "long_break" => AppState::LongBreak(false),
+                    _ => state,
+                };
+                let done = pomodoros % n;
+
+                match state {
+                    AppState::Work => done * 2,
+                    AppState::LongBreak(_) => self.len() - 1,
+                    // After the work session that took the count to `done`, unless that one is
+                    // followed by the long break
+                    AppState::ShortBreak if n == 1 => 0,
+                    AppState::ShortBreak if done == 0 => 1,
+                    AppState::ShortBreak => done * 2 - 1,
+                }
+            }
+            None => {
+                let work_per_cycle = self
+                    .phases
+                    .iter()
+                    .filter(|phase| phase.state == AppState::Work)
+                    .count() as u64;
+                // The first match and the first match with the right number of work sessions
+                // before it, by name and by state
+                let mut by_name = (None, None);
+                let mut by_state = (None, None);
+                let mut work_before = 0;
+
+                for (index, phase) in self.phases.iter().enumerate() {
+                    let in_place = work_per_cycle == 0
+                        || work_before % work_per_cycle == pomodoros % work_per_cycle;
+
+                    let note = |found: &mut (Option<usize>, Option<usize>), matches: bool| {
+                        if matches {
+                            found.0 = found.0.or(Some(index));
+
+                            if in_place {
+                                found.1 = found.1.or(Some(index));
+                            }
+                        }
+                    };
+                    note(&mut by_name, phase.name == name);
+                    note(&mut by_state, phase.state == state);
+
+                    if phase.state == AppState::Work {
+                        work_before += 1;
+                    }
+                }
+
+                by_name
+                    .1
+                    .or(by_name.0)
+                    .or(by_state.1)
+                    .or(by_state.0)
+                    .unwrap_or(0) as u64
+            }
+        }
+    }
+}
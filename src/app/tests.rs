@@ -0,0 +1,828 @@
+//! Simulations of the state machine, driven by a clock that only moves when it is told to
+
+use super::clock::Clock;
+use super::{App, AppState};
+use crate::config::Config;
+use crate::history::{History, PhaseOutcome, PhaseRecord};
+use crate::hooks::Hook;
+use crate::session::SessionFile;
+use crate::stats::{self, Grouping};
+use crate::test_dir::TestDir;
+
+use chrono::{Local, NaiveDate, TimeZone};
+
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+const MINUTE: u64 = 60;
+const HOUR: u64 = 60 * MINUTE;
+
+/// How often the UI updates the app
+const TICK: Duration = Duration::from_millis(250);
+
+#[derive(Default)]
+struct FakeClock {
+    now: Mutex<Duration>,
+    suspended: Mutex<Duration>,
+}
+
+impl FakeClock {
+    fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+
+    /// The system sleeping for `duration`, the monotonic time doesn't move
+    fn suspend(&self, duration: Duration) {
+        *self.suspended.lock().unwrap() += duration;
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&self) -> Duration {
+        *self.now.lock().unwrap()
+    }
+
+    fn suspended(&self) -> Duration {
+        *self.suspended.lock().unwrap()
+    }
+}
+
+/// An app on a fake clock, with a history and session file of its own that are removed when it is
+/// dropped
+struct Sim {
+    app: App,
+    clock: Arc<FakeClock>,
+    dir: TestDir,
+}
+
+impl Sim {
+    /// `config` is in the legacy format, the default config is used for anything it doesn't set
+    fn new(config: &str) -> Sim {
+        let dir = TestDir::new();
+        let config = format!(
+            "history_file={}\nsession_file={}\n{}",
+            dir.join("history.log").display(),
+            dir.join("session").display(),
+            config
+        );
+        let config = Config::new_from_config_string(&config).unwrap();
+        let clock = Arc::new(FakeClock::default());
+        let app = App::with_clock(Arc::new(config), Arc::clone(&clock) as Arc<dyn Clock>);
+
+        Sim { app, clock, dir }
+    }
+
+    /// Moves the clock on by `seconds`, updating after every tick like the UI does
+    fn run(&mut self, seconds: u64) {
+        let mut left = Duration::from_secs(seconds);
+
+        while left > Duration::new(0, 0) {
+            let step = TICK.min(left);
+            self.clock.advance(step);
+            self.app.update();
+            left -= step;
+        }
+    }
+
+    /// Moves the clock on by `seconds` with a single update at the end, like a UI that was held up
+    fn lag(&mut self, seconds: u64) {
+        self.clock.advance(Duration::from_secs(seconds));
+        self.app.update();
+    }
+
+    /// Suspends the system for `seconds` and updates once it wakes up
+    fn suspend(&mut self, seconds: u64) {
+        self.clock.suspend(Duration::from_secs(seconds));
+        self.app.update();
+    }
+
+    fn skip(&mut self) {
+        self.app
+            .transition_to_next_state(Duration::new(0, 0), PhaseOutcome::Skipped);
+        self.app.update();
+    }
+
+    fn rewind(&mut self) {
+        self.app.transition_to_prev_state(Duration::new(0, 0));
+        self.app.update();
+    }
+
+    fn state(&self) -> AppState {
+        *self.app.get_state()
+    }
+
+    fn elapsed(&self) -> u64 {
+        let (minutes, seconds) = self.app.time_elapsed();
+        minutes * MINUTE + seconds
+    }
+
+    /// The state, pomodoros and seconds elapsed, everything that should be the same however the
+    /// time was passed
+    fn summary(&self) -> (AppState, u64, u64) {
+        (self.state(), self.app.pomodoros(), self.elapsed())
+    }
+
+    /// The hooks triggered since this was last called
+    fn hooks(&mut self) -> Vec<Hook> {
+        self.app
+            .take_triggers()
+            .into_iter()
+            .map(|trigger| trigger.hook)
+            .collect()
+    }
+
+    fn history(&self) -> Vec<(AppState, PhaseOutcome)> {
+        History::new(self.dir.join("history.log"))
+            .read_records()
+            .unwrap()
+            .into_iter()
+            .map(|record| (record.state, record.outcome))
+            .collect()
+    }
+
+    /// Pomodoros today according to the history, as `tomato stats` would count them
+    fn pomodoros_in_history(&self) -> u64 {
+        let records = History::new(self.dir.join("history.log"))
+            .read_records()
+            .unwrap();
+
+        stats::progress_on(&records, Local::now().date_naive()).pomodoros
+    }
+}
+
+#[test]
+fn hours_of_ticks_follow_the_cycle() {
+    let mut sim = Sim::new("");
+    sim.run(8 * HOUR);
+
+    // A cycle is 4 work sessions, 3 short breaks and a long break (135 minutes), 8 hours is 3
+    // cycles, then 2 work sessions and short breaks and 15 minutes of the third work session
+    assert_eq!(sim.summary(), (AppState::Work, 14, 15 * MINUTE));
+    assert_eq!(sim.app.total_work_time(), (6, 5));
+
+    let history = sim.history();
+    assert_eq!(history.len(), 28);
+    assert!(history
+        .iter()
+        .all(|(_, outcome)| *outcome == PhaseOutcome::Completed));
+    assert_eq!(
+        history
+            .iter()
+            .filter(|(state, _)| *state == AppState::LongBreak(false))
+            .count(),
+        3
+    );
+}
+
+#[test]
+fn lag_moves_through_every_state_that_finished() {
+    let mut ticked = Sim::new("");
+    ticked.run(8 * HOUR + 7);
+
+    let mut lagged = Sim::new("");
+    lagged.lag(8 * HOUR + 7);
+
+    assert_eq!(lagged.summary(), ticked.summary());
+    assert_eq!(lagged.history(), ticked.history());
+
+    // The excess of each state carries over to the next one
+    let mut lagged = Sim::new("");
+    lagged.run(24 * MINUTE);
+    lagged.lag(31 * MINUTE + 30);
+    assert_eq!(lagged.summary(), (AppState::ShortBreak, 2, 30));
+}
+
+#[test]
+fn lag_fires_the_hooks_in_order() {
+    let mut sim = Sim::new("");
+    sim.hooks();
+    sim.lag(31 * MINUTE);
+
+    assert_eq!(
+        sim.hooks(),
+        [
+            Hook::WorkEnd,
+            Hook::BreakStart,
+            Hook::BreakEnd,
+            Hook::WorkStart
+        ]
+    );
+}
+
+#[test]
+fn long_breaks_follow_the_cadence() {
+    let mut sim = Sim::new("pomodoros_before_long_break=3");
+    let mut states = vec![sim.state()];
+
+    for _ in 0..12 {
+        sim.skip();
+        states.push(sim.state());
+    }
+
+    use AppState::*;
+    assert_eq!(
+        states,
+        [
+            Work,
+            ShortBreak,
+            Work,
+            ShortBreak,
+            Work,
+            LongBreak(false),
+            Work,
+            ShortBreak,
+            Work,
+            ShortBreak,
+            Work,
+            LongBreak(false),
+            Work
+        ]
+    );
+    assert_eq!(sim.app.pomodoros(), 6);
+}
+
+#[test]
+fn long_break_time_is_used() {
+    let mut sim = Sim::new("pomodoros_before_long_break=1\nlong_break_time=30m");
+    sim.run(25 * MINUTE);
+    assert_eq!(sim.summary(), (AppState::LongBreak(false), 1, 0));
+
+    sim.run(29 * MINUTE);
+    assert_eq!(sim.state(), AppState::LongBreak(false));
+
+    sim.run(MINUTE);
+    assert_eq!(sim.summary(), (AppState::Work, 1, 0));
+}
+
+#[test]
+fn elongating_a_break_starts_a_long_break() {
+    let mut sim = Sim::new("");
+    sim.run(27 * MINUTE);
+    sim.app.elongate_break();
+    sim.app.update();
+
+    // The 2 minutes of the short break are lost, the long break starts from the beginning
+    assert_eq!(sim.summary(), (AppState::LongBreak(true), 1, 0));
+
+    // It only works on a short break
+    sim.app.elongate_break();
+    assert_eq!(sim.state(), AppState::LongBreak(true));
+
+    sim.run(20 * MINUTE);
+    assert_eq!(sim.summary(), (AppState::Work, 1, 0));
+}
+
+#[test]
+fn paused_time_doesnt_count() {
+    let mut sim = Sim::new("");
+    sim.run(10 * MINUTE);
+    sim.hooks();
+
+    sim.app.toggle_pause();
+    sim.run(3 * HOUR);
+    assert!(sim.app.is_paused());
+    assert_eq!(sim.summary(), (AppState::Work, 0, 10 * MINUTE));
+    assert_eq!(sim.hooks(), [Hook::Pause]);
+
+    sim.app.toggle_pause();
+    sim.run(15 * MINUTE);
+    assert!(!sim.app.is_paused());
+    assert_eq!(sim.summary(), (AppState::ShortBreak, 1, 0));
+}
+
+#[test]
+fn seeking_moves_the_timer() {
+    let mut sim = Sim::new("");
+    sim.run(MINUTE);
+
+    sim.app.forward_timer(20 * MINUTE);
+    sim.app.update();
+    assert_eq!(sim.summary(), (AppState::Work, 0, 21 * MINUTE));
+
+    // Can't go back past the start of the state
+    sim.app.rewind_timer(30 * MINUTE);
+    sim.app.update();
+    assert_eq!(sim.summary(), (AppState::Work, 0, 0));
+
+    // Seeking past the end carries the rest over to the next state
+    sim.app.forward_timer(27 * MINUTE);
+    sim.app.update();
+    assert_eq!(sim.summary(), (AppState::ShortBreak, 1, 2 * MINUTE));
+
+    // Seeking while paused doesn't start the timer
+    sim.app.toggle_pause();
+    sim.app.rewind_timer(MINUTE);
+    sim.run(MINUTE);
+    assert!(sim.app.is_paused());
+    assert_eq!(sim.summary(), (AppState::ShortBreak, 1, MINUTE));
+}
+
+#[test]
+fn shortening_a_running_state_finishes_it() {
+    let mut sim = Sim::new("");
+    sim.run(10 * MINUTE);
+
+    // The 9 minutes past the new end go through a short break and then a 1 minute work session
+    sim.app.set_state_time(AppState::Work, MINUTE);
+    sim.app.update();
+    assert_eq!(sim.summary(), (AppState::ShortBreak, 2, 3 * MINUTE));
+    assert_eq!(sim.app.total_work_time(), (0, 2));
+}
+
+#[test]
+fn rewinding_with_no_pomodoros_restarts_work() {
+    let mut sim = Sim::new("");
+    sim.run(10 * MINUTE);
+    sim.hooks();
+
+    sim.rewind();
+    assert_eq!(sim.summary(), (AppState::Work, 0, 0));
+    assert_eq!(sim.hooks(), [Hook::Rewind, Hook::WorkEnd, Hook::WorkStart]);
+
+    // There is nothing before the first work session, so it stays there
+    sim.rewind();
+    assert_eq!(sim.summary(), (AppState::Work, 0, 0));
+    assert_eq!(
+        sim.history(),
+        [
+            (AppState::Work, PhaseOutcome::Rewound),
+            (AppState::Work, PhaseOutcome::Rewound)
+        ]
+    );
+
+    // A break can't be reached with no pomodoros, but if it is there is no pomodoro to take away
+    sim.app.transition_to_state(
+        1,
+        AppState::ShortBreak,
+        Duration::new(0, 0),
+        PhaseOutcome::Skipped,
+    );
+    sim.rewind();
+    assert_eq!(sim.summary(), (AppState::Work, 0, 0));
+}
+
+#[test]
+fn rewinding_takes_back_pomodoros() {
+    let mut sim = Sim::new("pomodoros_before_long_break=2");
+    sim.run(25 * MINUTE);
+    assert_eq!(sim.summary(), (AppState::ShortBreak, 1, 0));
+    assert_eq!(sim.app.total_work_time(), (0, 25));
+
+    sim.rewind();
+    assert_eq!(sim.summary(), (AppState::Work, 0, 0));
+    assert_eq!(sim.app.total_work_time(), (0, 0));
+
+    // Work, short break, work, long break
+    sim.run(25 * MINUTE + 5 * MINUTE + 25 * MINUTE + 20 * MINUTE);
+    assert_eq!(sim.summary(), (AppState::Work, 2, 0));
+
+    // Back to the long break without losing the pomodoro that led to it
+    sim.rewind();
+    assert_eq!(sim.summary(), (AppState::LongBreak(false), 2, 0));
+
+    sim.rewind();
+    assert_eq!(sim.summary(), (AppState::Work, 1, 0));
+
+    sim.rewind();
+    assert_eq!(sim.summary(), (AppState::ShortBreak, 1, 0));
+
+    sim.rewind();
+    sim.rewind();
+    sim.rewind();
+    assert_eq!(sim.summary(), (AppState::Work, 0, 0));
+    assert_eq!(sim.app.total_work_time(), (0, 0));
+}
+
+#[test]
+fn time_left_warning_runs_once_per_state() {
+    let mut sim = Sim::new("time_left_warning=2m");
+    let count = |hooks: Vec<Hook>| hooks.iter().filter(|hook| **hook == Hook::TimeLeft).count();
+
+    sim.run(22 * MINUTE);
+    assert_eq!(count(sim.hooks()), 0);
+
+    sim.run(2 * MINUTE);
+    assert_eq!(count(sim.hooks()), 1);
+
+    // Going back before the warning means it is needed again
+    sim.app.rewind_timer(5 * MINUTE);
+    sim.run(4 * MINUTE);
+    assert_eq!(count(sim.hooks()), 1);
+
+    // Not again in the rest of the work session, then once in the short break
+    sim.run(7 * MINUTE);
+    assert_eq!(count(sim.hooks()), 1);
+    assert_eq!(sim.summary(), (AppState::Work, 1, 0));
+}
+
+#[test]
+fn time_asleep_counts_by_default() {
+    let mut sim = Sim::new("");
+    sim.run(10 * MINUTE);
+
+    sim.suspend(17 * MINUTE);
+    assert_eq!(sim.summary(), (AppState::ShortBreak, 1, 2 * MINUTE));
+
+    // It doesn't count while paused
+    sim.app.toggle_pause();
+    sim.suspend(HOUR);
+    sim.app.toggle_pause();
+    sim.run(MINUTE);
+    assert_eq!(sim.summary(), (AppState::ShortBreak, 1, 3 * MINUTE));
+}
+
+#[test]
+fn suspending_can_pause_the_timer() {
+    let mut sim = Sim::new("suspend=pause");
+    sim.run(10 * MINUTE);
+    sim.hooks();
+
+    sim.suspend(HOUR);
+    assert!(sim.app.is_paused());
+    assert_eq!(sim.summary(), (AppState::Work, 0, 10 * MINUTE));
+    assert_eq!(sim.hooks(), []);
+
+    // A short gap isn't worth pausing for
+    sim.app.toggle_pause();
+    sim.suspend(1);
+    assert!(!sim.app.is_paused());
+    assert_eq!(sim.elapsed(), 10 * MINUTE + 1);
+}
+
+#[test]
+fn suspending_can_end_the_state() {
+    let mut sim = Sim::new("suspend=end");
+    sim.run(10 * MINUTE);
+
+    sim.suspend(5 * MINUTE);
+    assert_eq!(sim.summary(), (AppState::ShortBreak, 1, 0));
+
+    // Sleeping through the rest of the break completes it, without going any further
+    sim.run(MINUTE);
+    sim.suspend(HOUR);
+    assert_eq!(sim.summary(), (AppState::Work, 1, 0));
+
+    assert_eq!(
+        sim.history(),
+        [
+            (AppState::Work, PhaseOutcome::Skipped),
+            (AppState::ShortBreak, PhaseOutcome::Completed)
+        ]
+    );
+}
+
+#[test]
+fn overtime_keeps_the_work_session_going() {
+    let mut sim = Sim::new("overtime=true");
+    sim.hooks();
+    sim.run(28 * MINUTE + 12);
+
+    // Announced once when the time runs out, the work session hasn't ended yet
+    assert_eq!(sim.hooks(), [Hook::Overtime]);
+
+    assert_eq!(sim.summary(), (AppState::Work, 0, 25 * MINUTE));
+    assert_eq!(sim.app.overtime(), Some((3, 12)));
+    assert_eq!(sim.app.progress(), 1.0);
+    assert_eq!(sim.app.total_work_time(), (0, 25));
+    assert_eq!(sim.app.total_overtime(), (0, 3));
+
+    // Ending it starts the break from the beginning, the overtime is kept apart from the work
+    sim.app
+        .transition_to_next_state(Duration::new(0, 0), PhaseOutcome::Completed);
+    sim.app.update();
+    assert_eq!(sim.summary(), (AppState::ShortBreak, 1, 0));
+    assert_eq!(sim.app.overtime(), None);
+    assert_eq!(sim.app.total_work_time(), (0, 25));
+    assert_eq!(sim.app.total_overtime(), (0, 3));
+
+    let records = History::new(sim.dir.join("history.log"))
+        .read_records()
+        .unwrap();
+    assert_eq!(records[0].outcome, PhaseOutcome::Completed);
+    assert_eq!(records[0].planned, Duration::from_secs(25 * MINUTE));
+    assert_eq!(records[0].actual, Duration::from_secs(28 * MINUTE + 12));
+
+    // Breaks don't go into overtime
+    sim.run(6 * MINUTE);
+    assert_eq!(sim.summary(), (AppState::Work, 1, MINUTE));
+
+    // Going back to the work session takes its overtime away too
+    sim.rewind();
+    sim.rewind();
+    assert_eq!(sim.summary(), (AppState::Work, 0, 0));
+    assert_eq!(sim.app.total_overtime(), (0, 0));
+}
+
+#[test]
+fn overtime_can_be_paused() {
+    let mut sim = Sim::new("overtime=true");
+    sim.run(26 * MINUTE);
+
+    sim.app.toggle_pause();
+    sim.run(HOUR);
+    assert_eq!(sim.app.overtime(), Some((1, 0)));
+
+    sim.app.toggle_pause();
+    sim.run(MINUTE);
+    assert_eq!(sim.app.overtime(), Some((2, 0)));
+    assert_eq!(sim.summary(), (AppState::Work, 0, 25 * MINUTE));
+}
+
+#[test]
+fn states_can_wait_to_be_started() {
+    let mut sim = Sim::new("auto_start_break=false");
+    sim.run(25 * MINUTE + 30);
+
+    // The 30 seconds since the work session ended are dropped
+    assert!(sim.app.is_paused());
+    assert_eq!(sim.summary(), (AppState::ShortBreak, 1, 0));
+
+    sim.run(HOUR);
+    assert_eq!(sim.summary(), (AppState::ShortBreak, 1, 0));
+
+    // Starting it isn't resuming, it was never paused
+    sim.hooks();
+    sim.app.toggle_pause();
+    assert_eq!(sim.hooks(), []);
+
+    // Work sessions still start on their own
+    sim.run(6 * MINUTE);
+    assert!(!sim.app.is_paused());
+    assert_eq!(sim.summary(), (AppState::Work, 1, MINUTE));
+
+    // Skipping isn't held up
+    sim.skip();
+    assert!(!sim.app.is_paused());
+    assert_eq!(sim.state(), AppState::ShortBreak);
+}
+
+#[test]
+fn waiting_states_can_keep_the_excess() {
+    let mut sim = Sim::new("auto_start_work=false\nauto_start_break=false\nstart_excess=carry");
+    sim.run(24 * MINUTE);
+    sim.lag(3 * MINUTE);
+
+    // Time that passed before the break could be held counts towards it
+    assert!(sim.app.is_paused());
+    assert_eq!(sim.summary(), (AppState::ShortBreak, 1, 2 * MINUTE));
+
+    // A state that was finished before it could be held is moved through, the next one waits
+    sim.app.toggle_pause();
+    sim.lag(10 * MINUTE);
+    assert!(sim.app.is_paused());
+    assert_eq!(sim.summary(), (AppState::Work, 1, 7 * MINUTE));
+}
+
+#[test]
+fn carried_excess_leaves_the_waiting_state_to_start() {
+    let mut sim = Sim::new("auto_start_work=false\nauto_start_break=false\nstart_excess=carry");
+    sim.run(24 * MINUTE);
+    sim.lag(HOUR);
+
+    // More than the whole break passed, it still waits with a second left rather than being
+    // moved through without ever starting
+    assert!(sim.app.is_paused());
+    assert_eq!(sim.summary(), (AppState::ShortBreak, 1, 5 * MINUTE - 1));
+    assert_eq!(sim.history(), [(AppState::Work, PhaseOutcome::Completed)]);
+
+    sim.app.toggle_pause();
+    sim.run(1);
+    assert!(sim.app.is_paused());
+    assert_eq!(sim.summary(), (AppState::Work, 1, 0));
+}
+
+#[test]
+fn waiting_states_are_still_waiting_when_resumed() {
+    let mut sim = Sim::new("auto_start_break=false");
+    sim.run(25 * MINUTE);
+
+    let snapshot = SessionFile::new(sim.dir.join("session"))
+        .load()
+        .unwrap()
+        .unwrap();
+    assert!(snapshot.held);
+
+    // Starting it isn't resuming it
+    let mut app = App::resume(Arc::clone(&sim.app.config), &snapshot);
+    assert!(app.is_paused());
+    app.toggle_pause();
+    assert!(!app.is_paused());
+    assert!(app.take_triggers().is_empty());
+}
+
+#[test]
+fn snapshots_from_older_versions_are_resumed() {
+    let sim = Sim::new("work_time=50m");
+    let path = sim.dir.join("session");
+    fs::write(
+        &path,
+        "state=short_break\npomodoros=3\nelapsed_millis=60000\npaused=true\nphase_start=0\nsaved_at=0\n",
+    )
+    .unwrap();
+
+    // Each pomodoro counts as a work session of the current length
+    let snapshot = SessionFile::new(path).load().unwrap().unwrap();
+    let app = App::resume(Arc::clone(&sim.app.config), &snapshot);
+    assert_eq!(app.profile(), "default");
+    assert_eq!(*app.get_state(), AppState::ShortBreak);
+    assert_eq!(app.total_work_time(), (2, 30));
+    assert_eq!(app.total_overtime(), (0, 0));
+}
+
+#[test]
+fn long_cycles_are_worked_out_as_they_go() {
+    let mut sim = Sim::new(
+        "pomodoros_before_long_break=4000000000\n[profile.short]\npomodoros_before_long_break=2",
+    );
+    sim.skip();
+    sim.skip();
+    sim.skip();
+    assert_eq!(sim.summary(), (AppState::ShortBreak, 2, 0));
+
+    sim.rewind();
+    sim.rewind();
+    assert_eq!(sim.summary(), (AppState::ShortBreak, 1, 0));
+
+    // The second work session of the short profile is followed by the long break
+    sim.skip();
+    sim.app.switch_profile("short").unwrap();
+    sim.skip();
+    assert_eq!(sim.summary(), (AppState::LongBreak(false), 2, 0));
+
+    sim.app.switch_profile("default").unwrap();
+    sim.skip();
+    assert_eq!(sim.summary(), (AppState::Work, 2, 0));
+}
+
+const CYCLE: &str = "phases=warmup work review break work review long_break
+phase.warmup.time=10m
+phase.work.time=50m
+phase.review.time=5m
+phase.break.time=10m
+long_break_time=30m";
+
+#[test]
+fn custom_cycles_are_followed_in_order() {
+    let mut sim = Sim::new(CYCLE);
+    let mut phases = vec![(sim.app.phase().name.clone(), sim.summary())];
+
+    for minutes in &[10, 50, 5, 10, 50, 5, 30] {
+        sim.run(minutes * MINUTE);
+        phases.push((sim.app.phase().name.clone(), sim.summary()));
+    }
+
+    use AppState::*;
+    let expected = [
+        ("warmup", ShortBreak, 0),
+        ("work", Work, 0),
+        ("review", ShortBreak, 1),
+        ("break", ShortBreak, 1),
+        ("work", Work, 1),
+        ("review", ShortBreak, 2),
+        ("long_break", LongBreak(false), 2),
+        ("warmup", ShortBreak, 2),
+    ];
+    assert_eq!(
+        phases,
+        expected
+            .iter()
+            .map(|&(name, state, pomodoros)| (name.to_string(), (state, pomodoros, 0)))
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(sim.app.total_work_time(), (1, 40));
+    assert_eq!(sim.app.get_state_name(), "Warmup");
+}
+
+#[test]
+fn rewinding_walks_back_through_the_cycle() {
+    let mut sim = Sim::new(CYCLE);
+    sim.run(10 * MINUTE + 50 * MINUTE + 5 * MINUTE);
+    assert_eq!(sim.app.phase().name, "break");
+
+    // Both are breaks, so the pomodoro stays
+    sim.rewind();
+    assert_eq!(sim.app.phase().name, "review");
+    assert_eq!(sim.app.pomodoros(), 1);
+    assert_eq!(sim.pomodoros_in_history(), 1);
+
+    sim.rewind();
+    assert_eq!(sim.app.phase().name, "work");
+    assert_eq!(sim.app.pomodoros(), 0);
+    assert_eq!(sim.pomodoros_in_history(), 0);
+
+    // The warmup is at the start of the cycle, so there is nothing before it
+    sim.rewind();
+    sim.rewind();
+    assert_eq!(sim.app.phase().name, "warmup");
+    assert_eq!(sim.summary(), (AppState::ShortBreak, 0, 0));
+
+    // After a whole cycle the warmup comes after the long break
+    for _ in 0..7 {
+        sim.skip();
+    }
+    sim.rewind();
+    assert_eq!(sim.app.phase().name, "long_break");
+    assert_eq!(sim.app.pomodoros(), 2);
+}
+
+#[test]
+fn phases_have_their_own_colour_and_scripts() {
+    let mut sim = Sim::new(&format!(
+        "{}\non_break_start=break.sh\nphase.review.colour=blue\nphase.review.on_start=review.sh",
+        CYCLE
+    ));
+    let default = sim.app.theme().foreground;
+    let scripts = |sim: &mut Sim| {
+        sim.app
+            .take_invocations()
+            .into_iter()
+            .map(|invocation| invocation.script)
+            .collect::<Vec<_>>()
+    };
+
+    sim.run(10 * MINUTE);
+    assert_eq!(scripts(&mut sim), Vec::<String>::new());
+
+    sim.run(50 * MINUTE);
+    assert_eq!(scripts(&mut sim), ["review.sh"]);
+    assert_eq!(sim.app.theme().foreground, tui::style::Color::Blue);
+
+    sim.run(5 * MINUTE);
+    assert_eq!(scripts(&mut sim), ["break.sh"]);
+    assert_eq!(sim.app.theme().foreground, default);
+}
+
+#[test]
+fn switching_profile_keeps_the_place_in_the_cycle() {
+    let mut sim = Sim::new(&format!(
+        "pomodoros_before_long_break=2\n[profile.deep]\n{}",
+        CYCLE
+    ));
+    sim.run(25 * MINUTE + 5 * MINUTE + 25 * MINUTE);
+    assert_eq!(sim.summary(), (AppState::LongBreak(false), 2, 0));
+
+    // The long break in the other cycle comes after two work sessions as well
+    sim.app.switch_profile("deep").unwrap();
+    assert_eq!(sim.app.phase().name, "long_break");
+
+    sim.run(20 * MINUTE);
+    assert_eq!(sim.app.phase().name, "warmup");
+
+    // There is no warmup in the default cycle, so it takes the place of a short break
+    sim.app.switch_profile("default").unwrap();
+    assert_eq!(sim.app.phase().name, "short_break");
+
+    sim.run(10 * MINUTE);
+    assert_eq!(sim.summary(), (AppState::Work, 2, 0));
+}
+
+/// A finished phase starting at `minute` past 23:00 on the 1st of January (so past 60 is the
+/// next day), all of it planned time
+fn record(minute: u64, state: AppState, outcome: PhaseOutcome) -> PhaseRecord {
+    let start = SystemTime::from(Local.with_ymd_and_hms(2024, 1, 1, 23, 0, 0).unwrap())
+        + Duration::from_secs(minute * MINUTE);
+
+    PhaseRecord {
+        state,
+        start,
+        end: start + Duration::from_secs(5 * MINUTE),
+        planned: Duration::from_secs(25 * MINUTE),
+        actual: Duration::from_secs(5 * MINUTE),
+        outcome,
+        label: None,
+        phase: None,
+        took_back: None,
+    }
+}
+
+#[test]
+fn rewinds_only_undo_the_work_they_go_back_over() {
+    let records = [
+        record(0, AppState::Work, PhaseOutcome::Completed),
+        record(25, AppState::ShortBreak, PhaseOutcome::Completed),
+        record(30, AppState::Work, PhaseOutcome::Skipped),
+        // The next day
+        record(65, AppState::ShortBreak, PhaseOutcome::Rewound),
+        record(70, AppState::Work, PhaseOutcome::Completed),
+        record(95, AppState::ShortBreak, PhaseOutcome::Rewound),
+    ];
+    let first_day = NaiveDate::from_ymd_opt(2024, 1, 1);
+    let second_day = NaiveDate::from_ymd_opt(2024, 1, 2);
+
+    // The skipped session is taken back along with the skip and the streak it broke
+    let summaries = stats::summarise(&records[..4], Grouping::Day, None, None);
+    assert_eq!(summaries[0].pomodoros, 1);
+    assert_eq!(summaries[0].skipped_work, 0);
+    assert_eq!(summaries[0].longest_streak, 1);
+
+    // Neither rewind on the second day reaches back into the first
+    let summaries = stats::summarise(&records, Grouping::Day, second_day, second_day);
+    assert_eq!(summaries[0].pomodoros, 0);
+    assert_eq!(summaries[0].work_time, Duration::from_secs(0));
+    assert_eq!(summaries[0].longest_streak, 0);
+
+    let summaries = stats::summarise(&records, Grouping::Day, first_day, first_day);
+    assert_eq!(summaries[0].pomodoros, 1);
+    assert_eq!(summaries[0].skipped_work, 0);
+}
@@ -1,8 +1,13 @@
-use std::time::{Duration, SystemTime};
+use super::clock::Clock;
+
+use std::sync::Arc;
+use std::time::Duration;
 
 /// Poll-based timer
 pub struct Timer {
-    last_start: SystemTime,
+    clock: Arc<dyn Clock>,
+    /// Reading of the clock when the timer was last started
+    last_start: Duration,
     is_paused: bool,
     acc_duration: Duration,
     target_duration: Duration,
@@ -16,22 +21,19 @@ pub enum State {
 }
 
 impl Timer {
-    pub fn new(target_duration: Duration, paused: bool) -> Timer {
-        Timer {
-            last_start: SystemTime::now(),
-            is_paused: paused,
-            acc_duration: Duration::new(0, 0),
-            target_duration,
-        }
+    pub fn new(clock: Arc<dyn Clock>, target_duration: Duration, paused: bool) -> Timer {
+        Timer::new_with_acc_duration(clock, target_duration, paused, Duration::new(0, 0))
     }
 
     pub fn new_with_acc_duration(
+        clock: Arc<dyn Clock>,
         target_duration: Duration,
         paused: bool,
         acc_duration: Duration,
     ) -> Timer {
         Timer {
-            last_start: SystemTime::now(),
+            last_start: clock.now(),
+            clock,
             is_paused: paused,
             acc_duration,
             target_duration,
@@ -43,11 +45,8 @@ impl Timer {
             !self.is_paused,
             "Tried to pause a timer that was already paused"
         );
+        self.acc_duration += self.current_elapsed();
         self.is_paused = true;
-        self.acc_duration += self
-            .last_start
-            .elapsed()
-            .expect("SystemTime::elapsed failed");
     }
 
     pub fn start(&mut self) {
@@ -56,20 +55,26 @@ impl Timer {
             "Tried to start a timer that was already running"
         );
 
-        self.last_start = SystemTime::now();
+        self.last_start = self.clock.now();
         self.is_paused = false;
     }
 
-    pub fn get_state(&self) -> State {
-        let elapsed = if self.is_paused {
+    /// Time since the timer was last started, 0 if it is paused
+    fn current_elapsed(&self) -> Duration {
+        if self.is_paused {
             Duration::new(0, 0)
         } else {
-            self.last_start
-                .elapsed()
-                .expect("SystemTime::elapsed failed")
-        };
+            self.clock.now().saturating_sub(self.last_start)
+        }
+    }
+
+    /// Total time the timer has been running for, including any time past the target duration
+    pub fn total_duration(&self) -> Duration {
+        self.acc_duration + self.current_elapsed()
+    }
 
-        let total_duration = self.acc_duration + elapsed;
+    pub fn get_state(&self) -> State {
+        let total_duration = self.total_duration();
         if total_duration < self.target_duration {
             if self.is_paused {
                 State::Paused
@@ -92,19 +97,11 @@ impl Timer {
 
     /// Returns fraction of the way through the time (between 0.0 and 1.0), time elapsed, and time remaining
     pub fn get_progress_data(&self) -> (f64, Duration, Duration) {
-        let current_elapsed = if self.is_paused {
-            Duration::new(0, 0)
-        } else {
-            self.last_start
-                .elapsed()
-                .expect("SystemTime::elapsed failed")
-        };
-
-        let total_duration = self.acc_duration + current_elapsed;
+        let total_duration = self.total_duration();
         (
             total_duration.as_millis() as f64 / self.target_duration.as_millis() as f64,
             total_duration,
-            self.target_duration - total_duration,
+            self.target_duration.saturating_sub(total_duration),
         )
     }
 
@@ -112,8 +109,23 @@ impl Timer {
         self.is_paused
     }
 
+    pub fn target_duration(&self) -> Duration {
+        self.target_duration
+    }
+
+    /// Changes how long the timer runs for, if it has already run for longer then it finishes on
+    /// the next update
+    pub fn set_target_duration(&mut self, target_duration: Duration) {
+        self.target_duration = target_duration;
+    }
+
+    /// Total time the timer has been running for, capped at the target duration
+    pub fn elapsed(&self) -> Duration {
+        std::cmp::min(self.total_duration(), self.target_duration)
+    }
+
     pub fn reset(&mut self, paused: bool) {
-        self.last_start = SystemTime::now();
+        self.last_start = self.clock.now();
         self.acc_duration = Duration::new(0, 0);
         self.is_paused = paused;
     }
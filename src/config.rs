@@ -1,94 +1,1299 @@
+use crate::duration;
+use crate::goal;
+use crate::hooks::HOOKS;
+use crate::keymap::{self, KeyMap};
+use crate::theme;
+
 use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+mod toml_file;
+
+/// The type of value a setting accepts
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    /// Duration greater than 0 (see `duration::parse_seconds`), stored in seconds
+    Duration,
+    /// Duration that may be 0, stored in seconds
+    Seconds,
+    /// Whole number greater than 0
+    Count,
+    /// Whole number from 0 to 100 (with or without a `%`)
+    Percent,
+    /// Any text (paths, scripts, ...)
+    Text,
+    /// Colour name or `#rrggbb` (see `theme::parse_colour`)
+    Colour,
+    /// Keys separated by spaces or a list of keys (see `keymap::parse_key`)
+    Keys,
+    /// `http://` or `https://` URLs separated by spaces or a list of them
+    Urls,
+    /// Phase names separated by spaces or a list of them (see `check_phase_name`)
+    Phases,
+    /// Number of pomodoros or an amount of work time (see `goal::parse`)
+    Goal,
+    /// `true` or `false` (`yes`/`no` and `on`/`off` work too), stored as 1 or 0
+    Bool,
+    /// One of the words
+    Choice(&'static [&'static str]),
+}
+
+struct Setting {
+    key: &'static str,
+    kind: Kind,
+    /// Whether the setting can be given a different value in each profile
+    per_profile: bool,
+}
+
+/// Every key that can appear in the config file
+const SETTINGS: &[Setting] = &[
+    Setting {
+        key: "work_time",
+        kind: Kind::Duration,
+        per_profile: true,
+    },
+    Setting {
+        key: "short_break_time",
+        kind: Kind::Duration,
+        per_profile: true,
+    },
+    Setting {
+        key: "long_break_time",
+        kind: Kind::Duration,
+        per_profile: true,
+    },
+    Setting {
+        key: "pomodoros_before_long_break",
+        kind: Kind::Count,
+        per_profile: true,
+    },
+    Setting {
+        key: "phases",
+        kind: Kind::Phases,
+        per_profile: true,
+    },
+    // `*` is the name of the phase
+    Setting {
+        key: "phase.*.kind",
+        kind: Kind::Choice(&["work", "short_break", "long_break"]),
+        per_profile: true,
+    },
+    Setting {
+        key: "phase.*.time",
+        kind: Kind::Duration,
+        per_profile: true,
+    },
+    Setting {
+        key: "phase.*.colour",
+        kind: Kind::Colour,
+        per_profile: true,
+    },
+    Setting {
+        key: "phase.*.on_start",
+        kind: Kind::Text,
+        per_profile: true,
+    },
+    Setting {
+        key: "phase.*.on_end",
+        kind: Kind::Text,
+        per_profile: true,
+    },
+    Setting {
+        key: "on_start",
+        kind: Kind::Text,
+        per_profile: true,
+    },
+    Setting {
+        key: "on_quit",
+        kind: Kind::Text,
+        per_profile: true,
+    },
+    Setting {
+        key: "on_work_start",
+        kind: Kind::Text,
+        per_profile: true,
+    },
+    Setting {
+        key: "on_work_end",
+        kind: Kind::Text,
+        per_profile: true,
+    },
+    Setting {
+        key: "on_break_start",
+        kind: Kind::Text,
+        per_profile: true,
+    },
+    Setting {
+        key: "on_break_end",
+        kind: Kind::Text,
+        per_profile: true,
+    },
+    Setting {
+        key: "on_pause",
+        kind: Kind::Text,
+        per_profile: true,
+    },
+    Setting {
+        key: "on_resume",
+        kind: Kind::Text,
+        per_profile: true,
+    },
+    Setting {
+        key: "on_skip",
+        kind: Kind::Text,
+        per_profile: true,
+    },
+    Setting {
+        key: "on_rewind",
+        kind: Kind::Text,
+        per_profile: true,
+    },
+    Setting {
+        key: "on_elongate",
+        kind: Kind::Text,
+        per_profile: true,
+    },
+    Setting {
+        key: "on_time_left",
+        kind: Kind::Text,
+        per_profile: true,
+    },
+    Setting {
+        key: "on_goal_reached",
+        kind: Kind::Text,
+        per_profile: true,
+    },
+    Setting {
+        key: "on_overtime",
+        kind: Kind::Text,
+        per_profile: true,
+    },
+    // `*` is the name of an event or `default`
+    Setting {
+        key: "hook_timeout.*",
+        kind: Kind::Duration,
+        per_profile: true,
+    },
+    Setting {
+        key: "time_left_warning",
+        kind: Kind::Duration,
+        per_profile: true,
+    },
+    Setting {
+        key: "overtime",
+        kind: Kind::Bool,
+        per_profile: true,
+    },
+    Setting {
+        key: "auto_start_work",
+        kind: Kind::Bool,
+        per_profile: true,
+    },
+    Setting {
+        key: "auto_start_break",
+        kind: Kind::Bool,
+        per_profile: true,
+    },
+    Setting {
+        key: "start_excess",
+        kind: Kind::Choice(&["discard", "carry"]),
+        per_profile: true,
+    },
+    Setting {
+        key: "suspend",
+        kind: Kind::Choice(&["count", "pause", "end"]),
+        per_profile: false,
+    },
+    Setting {
+        key: "default_profile",
+        kind: Kind::Text,
+        per_profile: false,
+    },
+    Setting {
+        key: "history_file",
+        kind: Kind::Text,
+        per_profile: false,
+    },
+    Setting {
+        key: "session_file",
+        kind: Kind::Text,
+        per_profile: false,
+    },
+    Setting {
+        key: "resume_threshold",
+        kind: Kind::Seconds,
+        per_profile: false,
+    },
+    Setting {
+        key: "socket_file",
+        kind: Kind::Text,
+        per_profile: false,
+    },
+    Setting {
+        key: "tasks_file",
+        kind: Kind::Text,
+        per_profile: false,
+    },
+    Setting {
+        key: "hook_log_file",
+        kind: Kind::Text,
+        per_profile: false,
+    },
+    Setting {
+        key: "daily_goal",
+        kind: Kind::Goal,
+        per_profile: false,
+    },
+    Setting {
+        key: "notifications.enabled",
+        kind: Kind::Bool,
+        per_profile: false,
+    },
+    Setting {
+        key: "notifications.summary",
+        kind: Kind::Text,
+        per_profile: false,
+    },
+    Setting {
+        key: "notifications.body",
+        kind: Kind::Text,
+        per_profile: false,
+    },
+    Setting {
+        key: "notifications.urgency",
+        kind: Kind::Choice(&["low", "normal", "critical"]),
+        per_profile: false,
+    },
+    Setting {
+        key: "notifications.actions",
+        kind: Kind::Bool,
+        per_profile: false,
+    },
+    Setting {
+        key: "webhooks",
+        kind: Kind::Urls,
+        per_profile: false,
+    },
+    Setting {
+        key: "webhook_attempts",
+        kind: Kind::Count,
+        per_profile: false,
+    },
+    Setting {
+        key: "webhook_timeout",
+        kind: Kind::Duration,
+        per_profile: false,
+    },
+    // `*` is the name of the header
+    Setting {
+        key: "webhook_headers.*",
+        kind: Kind::Text,
+        per_profile: false,
+    },
+    Setting {
+        key: "sound.enabled",
+        kind: Kind::Bool,
+        per_profile: false,
+    },
+    Setting {
+        key: "sound.volume",
+        kind: Kind::Percent,
+        per_profile: false,
+    },
+    Setting {
+        key: "sound.work_start",
+        kind: Kind::Text,
+        per_profile: false,
+    },
+    Setting {
+        key: "sound.break_start",
+        kind: Kind::Text,
+        per_profile: false,
+    },
+    Setting {
+        key: "sound.time_left",
+        kind: Kind::Text,
+        per_profile: false,
+    },
+    Setting {
+        key: "sound.ticking",
+        kind: Kind::Bool,
+        per_profile: false,
+    },
+    Setting {
+        key: "sound.tick",
+        kind: Kind::Text,
+        per_profile: false,
+    },
+    Setting {
+        key: "theme.foreground",
+        kind: Kind::Colour,
+        per_profile: true,
+    },
+    Setting {
+        key: "theme.background",
+        kind: Kind::Colour,
+        per_profile: true,
+    },
+    Setting {
+        key: "theme.overtime",
+        kind: Kind::Colour,
+        per_profile: true,
+    },
+    Setting {
+        key: "keybindings.quit",
+        kind: Kind::Keys,
+        per_profile: false,
+    },
+    Setting {
+        key: "keybindings.toggle_pause",
+        kind: Kind::Keys,
+        per_profile: false,
+    },
+    Setting {
+        key: "keybindings.next",
+        kind: Kind::Keys,
+        per_profile: false,
+    },
+    Setting {
+        key: "keybindings.prev",
+        kind: Kind::Keys,
+        per_profile: false,
+    },
+    Setting {
+        key: "keybindings.elongate",
+        kind: Kind::Keys,
+        per_profile: false,
+    },
+    Setting {
+        key: "keybindings.next_profile",
+        kind: Kind::Keys,
+        per_profile: false,
+    },
+    Setting {
+        key: "keybindings.help",
+        kind: Kind::Keys,
+        per_profile: false,
+    },
+    Setting {
+        key: "keybindings.command",
+        kind: Kind::Keys,
+        per_profile: false,
+    },
+    Setting {
+        key: "keybindings.label",
+        kind: Kind::Keys,
+        per_profile: false,
+    },
+    Setting {
+        key: "keybindings.task_down",
+        kind: Kind::Keys,
+        per_profile: false,
+    },
+    Setting {
+        key: "keybindings.task_up",
+        kind: Kind::Keys,
+        per_profile: false,
+    },
+    Setting {
+        key: "keybindings.task_start",
+        kind: Kind::Keys,
+        per_profile: false,
+    },
+    Setting {
+        key: "keybindings.task_done",
+        kind: Kind::Keys,
+        per_profile: false,
+    },
+    Setting {
+        key: "keybindings.task_estimate",
+        kind: Kind::Keys,
+        per_profile: false,
+    },
+    Setting {
+        key: "keybindings.hook_log",
+        kind: Kind::Keys,
+        per_profile: false,
+    },
+    // `*` is the offset to seek by (see `keymap::parse_seek`)
+    Setting {
+        key: "keybindings.seek.*",
+        kind: Kind::Keys,
+        per_profile: false,
+    },
+];
+
+fn find_setting(key: &str) -> Option<&'static Setting> {
+    SETTINGS
+        .iter()
+        .find(|setting| match wildcard(setting.key, key) {
+            Some(_) => true,
+            None => setting.key == key,
+        })
+}
+
+/// For a setting with a `*` in its key, the text before the `*` and the part of `key` that the
+/// `*` matches (which can't be empty)
+fn wildcard<'a>(setting: &'static str, key: &'a str) -> Option<(&'static str, &'a str)> {
+    let (prefix, suffix) = setting.split_once('*')?;
+
+    if key.len() > prefix.len() + suffix.len() && key.starts_with(prefix) && key.ends_with(suffix) {
+        Some((prefix, &key[prefix.len()..key.len() - suffix.len()]))
+    } else {
+        None
+    }
+}
+
+/// Name of the profile made up of the settings outside of any `[profile.NAME]` section
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// A value as it was written in the file, before it has been checked
+#[derive(Clone, Debug, PartialEq)]
+enum RawValue {
+    /// Everything in the `key=value` format is text
+    Text(String),
+    Integer(i64),
+    Boolean(bool),
+    List(Vec<String>),
+}
+
+/// A key and value read from a config file that hasn't been checked against the settings yet
+struct Entry {
+    profile: Option<String>,
+    key: String,
+    value: RawValue,
+    /// Line and column of the key
+    position: (usize, usize),
+    /// Line and column of the value
+    value_position: (usize, usize),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Int(u64),
+    Text(String),
+    List(Vec<String>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// A problem found in the config, lines and columns start at 1
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub column: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: {}: {}",
+            self.line,
+            self.column,
+            match self.severity {
+                Severity::Warning => "warning",
+                Severity::Error => "error",
+            },
+            self.message
+        )
+    }
+}
+
+/// Returned when the config has at least one error, contains every diagnostic (including
+/// warnings)
+#[derive(Debug)]
+pub struct ConfigError {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Invalid config:")?;
+
+        for diagnostic in &self.diagnostics {
+            writeln!(f, "  {}", diagnostic)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Settings from a `[profile.NAME]` section, these take priority over the settings outside of
+/// any section
+#[derive(Clone, Debug)]
+struct Profile {
+    name: String,
+    values: HashMap<String, Value>,
+}
 
 #[derive(Clone, Debug)]
 pub struct Config {
-    ints: HashMap<String, isize>,
-    floats: HashMap<String, f64>,
-    strings: HashMap<String, String>,
+    values: HashMap<String, Value>,
+    profiles: Vec<Profile>,
+    /// Values from the command line, these take priority over everything in the file (including
+    /// the profile, even after switching to another one)
+    overrides: HashMap<String, Value>,
+    /// Index into `profiles` of the profile being used (`None` for the default profile)
+    active_profile: Option<usize>,
+    warnings: Vec<Diagnostic>,
 }
 
 impl Config {
     pub fn new() -> Config {
         Config {
-            ints: HashMap::new(),
-            strings: HashMap::new(),
-            floats: HashMap::new(),
+            values: HashMap::new(),
+            profiles: Vec::new(),
+            overrides: HashMap::new(),
+            active_profile: None,
+            warnings: Vec::new(),
         }
     }
 
-    pub fn new_from_config_file<P: std::convert::AsRef<std::path::Path>>(
-        path: P,
-    ) -> std::io::Result<Config> {
-        use std::fs;
-        let s = fs::read_to_string(path)?;
-
-        Ok(Config::new_from_config_string(&s))
-    }
-
-    pub fn new_from_config_string(s: &str) -> Config {
-        let mut ints: HashMap<String, isize> = HashMap::new();
-        let mut floats: HashMap<String, f64> = HashMap::new();
-        let mut strings: HashMap<String, String> = HashMap::new();
+    /// Parses `key=value` lines, lines starting with `#` and empty lines are ignored.
+    /// Only the first `=` separates the key from the value so values may contain `=`.
+    ///
+    /// A `[profile.NAME]` line starts a profile, every key after it (until the next profile)
+    /// belongs to that profile.
+    pub fn new_from_config_string(s: &str) -> Result<Config, ConfigError> {
+        let mut entries: Vec<Entry> = Vec::new();
+        let mut diagnostics: Vec<Diagnostic> = Vec::new();
+        // Profile that keys are currently being added to
+        let mut section: Option<String> = None;
+        // Set after an invalid section so its keys don't end up in the wrong place
+        let mut skipping_section = false;
 
         for (i, line) in s.lines().enumerate() {
+            let line_number = i + 1;
             let trim_line = line.trim_start();
+            let indent = line.len() - trim_line.len();
+            // Columns are counted in characters from 1, offsets are in bytes from the start of
+            // `trim_line`
+            let column = |offset: usize| line[..indent + offset].chars().count() + 1;
 
-            // Ignore empty line
-            if trim_line == "" {
+            // Ignore empty lines and lines where the first non-whitespace char is `#` since
+            // that is reserved for comments
+            if trim_line.is_empty() || trim_line.starts_with('#') {
                 continue;
             }
 
-            // Ignore lines where the first non-whitespace char is `#` since that is reserved for comments
-            if trim_line.get(0..1) != Some("#") {
-                let parts = trim_line.split('=').collect::<Vec<&str>>();
+            let mut error = |column: usize, message: String| {
+                diagnostics.push(Diagnostic {
+                    line: line_number,
+                    column,
+                    severity: Severity::Error,
+                    message,
+                })
+            };
 
-                // TODO: Come up with error strategy (maybe callback)
-                if parts.len() != 2 {
-                    println!(
-                        "Invalid format on line - too many sections - (skipping) {}: \"{}\"",
-                        i, line
-                    );
-                    continue;
+            if trim_line.starts_with('[') {
+                skipping_section = false;
+
+                match parse_section(trim_line) {
+                    Ok(name) => section = Some(name.to_string()),
+                    Err(message) => {
+                        error(column(0), message);
+                        // Skip the keys in this section rather than reporting errors for each
+                        skipping_section = true;
+                    }
                 }
+                continue;
+            }
 
-                let key = parts[0].trim();
+            if skipping_section {
+                continue;
+            }
 
-                if key == "" {
-                    println!(
-                        "Invalid format on line - no key - (skipping) {}: \"{}\"",
-                        i, line
-                    );
+            let equals = match trim_line.find('=') {
+                Some(equals) => equals,
+                None => {
+                    error(column(0), "expected `key=value`".to_string());
                     continue;
                 }
+            };
 
-                if let Ok(value) = parts[1].parse::<isize>() {
-                    ints.insert(key.to_string(), value);
-                } else if let Ok(value) = parts[1].parse::<f64>() {
-                    floats.insert(key.to_string(), value);
-                } else {
-                    strings.insert(key.to_string(), parts[1].to_string());
+            let key = trim_line[..equals].trim_end();
+
+            if key.is_empty() {
+                error(column(0), "missing key".to_string());
+                continue;
+            }
+
+            let raw_value = &trim_line[equals + 1..];
+
+            entries.push(Entry {
+                profile: section.clone(),
+                key: key.to_string(),
+                value: RawValue::Text(raw_value.trim().to_string()),
+                position: (line_number, column(0)),
+                value_position: (
+                    line_number,
+                    column(equals + 1 + raw_value.len() - raw_value.trim_start().len()),
+                ),
+            });
+        }
+
+        Config::from_entries(entries, diagnostics)
+    }
+
+    /// Parses a TOML config, see `toml_file` for how the tables map onto settings
+    pub fn new_from_toml_string(s: &str) -> Result<Config, ConfigError> {
+        let (entries, diagnostics) = toml_file::parse(s);
+
+        Config::from_entries(entries, diagnostics)
+    }
+
+    /// Chooses the format based on the extension of the path (`.toml` or the `key=value` format
+    /// for anything else)
+    pub fn new_from_path_and_string(path: &Path, s: &str) -> Result<Config, ConfigError> {
+        if path.extension().map(|ext| ext == "toml").unwrap_or(false) {
+            Config::new_from_toml_string(s)
+        } else {
+            Config::new_from_config_string(s)
+        }
+    }
+
+    /// Checks every entry against the settings, `diagnostics` are any problems that were already
+    /// found while reading the file
+    fn from_entries(
+        entries: Vec<Entry>,
+        mut diagnostics: Vec<Diagnostic>,
+    ) -> Result<Config, ConfigError> {
+        let mut config = Config::new();
+        // Where each key was last set (per profile)
+        let mut set_on: HashMap<(Option<String>, String), (usize, usize)> = HashMap::new();
+        // Where `default_profile` was set so it can be checked once every profile is known
+        let mut default_profile_position = (0, 0);
+
+        for entry in entries {
+            let mut diagnostic = |(line, column): (usize, usize), severity, message| {
+                diagnostics.push(Diagnostic {
+                    line,
+                    column,
+                    severity,
+                    message,
+                })
+            };
+
+            let (setting, value) =
+                match check_entry(&entry.key, entry.profile.is_some(), &entry.value) {
+                    Ok(checked) => checked,
+                    Err(Problem::Ignored(message)) => {
+                        diagnostic(
+                            entry.position,
+                            Severity::Warning,
+                            format!("{} (ignored)", message),
+                        );
+                        continue;
+                    }
+                    Err(Problem::Key(message)) => {
+                        diagnostic(
+                            entry.position,
+                            Severity::Error,
+                            format!("invalid key `{}`: {}", entry.key, message),
+                        );
+                        continue;
+                    }
+                    Err(Problem::Value(message)) => {
+                        diagnostic(
+                            entry.value_position,
+                            Severity::Error,
+                            format!("invalid value for `{}`: {}", entry.key, message),
+                        );
+                        continue;
+                    }
+                };
+
+            if let Some((previous, _)) =
+                set_on.insert((entry.profile.clone(), entry.key.clone()), entry.position)
+            {
+                diagnostic(
+                    entry.position,
+                    Severity::Warning,
+                    format!(
+                        "`{}` was already set on line {}, this value replaces it",
+                        entry.key, previous
+                    ),
+                );
+            }
+
+            if setting.key == "default_profile" {
+                default_profile_position = entry.value_position;
+            }
+
+            match entry.profile {
+                Some(name) => {
+                    let index = match config
+                        .profiles
+                        .iter()
+                        .position(|profile| profile.name == name)
+                    {
+                        Some(index) => index,
+                        None => {
+                            config.profiles.push(Profile {
+                                name,
+                                values: HashMap::new(),
+                            });
+                            config.profiles.len() - 1
+                        }
+                    };
+
+                    config.profiles[index].values.insert(entry.key, value);
+                }
+                None => {
+                    config.values.insert(entry.key, value);
                 }
             }
         }
 
-        Config {
-            ints,
-            floats,
-            strings,
+        if let Some(name) = config.get_string("default_profile").cloned() {
+            if let Err(message) = config.set_profile(&name) {
+                diagnostics.push(Diagnostic {
+                    line: default_profile_position.0,
+                    column: default_profile_position.1,
+                    severity: Severity::Error,
+                    message,
+                });
+            }
+        }
+
+        for conflict in KeyMap::build(&config).1 {
+            let (line, column) = set_on
+                .get(&(None, conflict.setting))
+                .copied()
+                .unwrap_or((0, 0));
+
+            diagnostics.push(Diagnostic {
+                line,
+                column,
+                severity: conflict.severity,
+                message: conflict.message,
+            });
+        }
+
+        diagnostics.sort_by_key(|diagnostic| (diagnostic.line, diagnostic.column));
+
+        if diagnostics
+            .iter()
+            .any(|diagnostic| diagnostic.severity == Severity::Error)
+        {
+            return Err(ConfigError { diagnostics });
         }
+
+        config.warnings = diagnostics;
+        Ok(config)
     }
 
-    pub fn get_int(&self, key: &str) -> Option<isize> {
-        self.ints.get(key).cloned()
+    /// Sets a value on top of whatever was in the file (used for command line overrides), it is
+    /// validated in the same way as the file. It stays set whichever profile is used.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let value = RawValue::Text(value.trim().to_string());
+        let (_, value) = check_entry(key, false, &value).map_err(|problem| match problem {
+            Problem::Ignored(message) | Problem::Value(message) => message,
+            Problem::Key(message) => format!("invalid key `{}`: {}", key, message),
+        })?;
+        self.overrides.insert(key.to_string(), value);
+
+        Ok(())
     }
 
-    // Will use this at some point, keeping for consistency
-    #[allow(dead_code)]
-    pub fn get_float<'a>(&'a self, key: &str) -> Option<&'a f64> {
-        self.floats.get(key)
+    /// Problems that weren't bad enough to stop the config from loading
+    pub fn warnings(&self) -> &[Diagnostic] {
+        &self.warnings
+    }
+
+    /// The default profile followed by every profile in the order they appear in the file
+    pub fn profile_names(&self) -> Vec<&str> {
+        std::iter::once(DEFAULT_PROFILE)
+            .chain(self.profiles.iter().map(|profile| profile.name.as_str()))
+            .collect()
+    }
+
+    pub fn active_profile(&self) -> &str {
+        match self.active_profile {
+            Some(index) => &self.profiles[index].name,
+            None => DEFAULT_PROFILE,
+        }
+    }
+
+    /// Makes the getters use the values from the profile
+    pub fn set_profile(&mut self, name: &str) -> Result<(), String> {
+        if name == DEFAULT_PROFILE {
+            self.active_profile = None;
+            return Ok(());
+        }
+
+        match self
+            .profiles
+            .iter()
+            .position(|profile| profile.name == name)
+        {
+            Some(index) => {
+                self.active_profile = Some(index);
+                Ok(())
+            }
+            None => Err(format!("there is no profile called `{}`", name)),
+        }
+    }
+
+    /// A copy of the config using a different profile
+    pub fn with_profile(&self, name: &str) -> Result<Config, String> {
+        let mut config = self.clone();
+        config.set_profile(name)?;
+        Ok(config)
+    }
+
+    fn get(&self, key: &str) -> Option<&Value> {
+        self.overrides
+            .get(key)
+            .or_else(|| {
+                self.active_profile
+                    .and_then(|index| self.profiles[index].values.get(key))
+            })
+            .or_else(|| self.values.get(key))
+    }
+
+    pub fn get_int(&self, key: &str) -> Option<u64> {
+        match self.get(key) {
+            Some(Value::Int(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        self.get_int(key).map(|value| value != 0)
+    }
+
+    pub fn get_list(&self, key: &str) -> Option<&[String]> {
+        match self.get(key) {
+            Some(Value::List(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Every key that has been set starting with `prefix`, in alphabetical order
+    pub fn keys_with_prefix(&self, prefix: &str) -> Vec<String> {
+        let profile = self
+            .active_profile
+            .map(|index| &self.profiles[index].values);
+
+        let mut keys = self
+            .overrides
+            .keys()
+            .chain(profile.into_iter().flat_map(|values| values.keys()))
+            .chain(self.values.keys())
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        keys.sort();
+        keys.dedup();
+        keys
     }
 
     pub fn get_string<'a>(&'a self, key: &str) -> Option<&'a String> {
-        self.strings.get(key)
+        match self.get(key) {
+            Some(Value::Text(value)) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+/// Why a key and value can't be used
+enum Problem {
+    /// The key isn't a setting (or can't be set in a profile), it is ignored
+    Ignored(String),
+    /// The part of the key that matched the `*` of its setting isn't valid
+    Key(String),
+    Value(String),
+}
+
+/// Checks a key and its value against the settings, the same way whether they came from a file
+/// or the command line
+fn check_entry(
+    key: &str,
+    in_profile: bool,
+    value: &RawValue,
+) -> Result<(&'static Setting, Value), Problem> {
+    let setting =
+        find_setting(key).ok_or_else(|| Problem::Ignored(format!("unknown key `{}`", key)))?;
+
+    if in_profile && !setting.per_profile {
+        return Err(Problem::Ignored(format!(
+            "`{}` can't be set in a profile",
+            key
+        )));
+    }
+
+    let value = parse_value(setting.kind, value).map_err(Problem::Value)?;
+
+    if let Some((prefix, rest)) = wildcard(setting.key, key) {
+        check_wildcard(prefix, rest).map_err(Problem::Key)?;
+    }
+
+    Ok((setting, value))
+}
+
+/// Returns the name of the profile from a `[profile.NAME]` line
+fn parse_section(line: &str) -> Result<&str, String> {
+    let inner = line
+        .trim_end()
+        .strip_prefix('[')
+        .and_then(|line| line.strip_suffix(']'))
+        .ok_or_else(|| "expected a section like `[profile.NAME]`".to_string())?
+        .trim();
+
+    let name = inner
+        .strip_prefix("profile.")
+        .ok_or_else(|| format!("unknown section `[{}]`, expected `[profile.NAME]`", inner))?;
+
+    check_profile_name(name)?;
+
+    Ok(name)
+}
+
+fn check_profile_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        return Err(format!("invalid profile name `{}`", name));
+    }
+
+    if name == DEFAULT_PROFILE {
+        return Err(format!(
+            "`{}` is the name of the settings outside of any profile",
+            DEFAULT_PROFILE
+        ));
+    }
+
+    Ok(())
+}
+
+fn parse_value(kind: Kind, value: &RawValue) -> Result<Value, String> {
+    match (kind, value) {
+        (Kind::Keys, RawValue::Text(value)) => parse_keys(
+            &value
+                .split_whitespace()
+                .map(str::to_string)
+                .collect::<Vec<_>>(),
+        ),
+        (Kind::Keys, RawValue::List(keys)) => parse_keys(keys),
+        (Kind::Urls, RawValue::Text(value)) => parse_urls(
+            &value
+                .split_whitespace()
+                .map(str::to_string)
+                .collect::<Vec<_>>(),
+        ),
+        (Kind::Urls, RawValue::List(urls)) => parse_urls(urls),
+        (Kind::Phases, RawValue::Text(value)) => parse_phases(
+            &value
+                .split_whitespace()
+                .map(str::to_string)
+                .collect::<Vec<_>>(),
+        ),
+        (Kind::Phases, RawValue::List(names)) => parse_phases(names),
+        (_, RawValue::List(_)) => Err("expected a single value, not a list".to_string()),
+        (Kind::Text, RawValue::Text(value)) => {
+            if value.is_empty() {
+                return Err("expected a value".to_string());
+            }
+
+            Ok(Value::Text(value.to_string()))
+        }
+        (Kind::Colour, RawValue::Text(value)) => match theme::parse_colour(value) {
+            Some(_) => Ok(Value::Text(value.to_string())),
+            None => Err(format!(
+                "unknown colour \"{}\", expected a name like `blue` or `#rrggbb`",
+                value
+            )),
+        },
+        (Kind::Bool, RawValue::Boolean(value)) => Ok(Value::Int(*value as u64)),
+        (Kind::Bool, RawValue::Text(value)) => match value.to_lowercase().as_str() {
+            "true" | "yes" | "on" => Ok(Value::Int(1)),
+            "false" | "no" | "off" => Ok(Value::Int(0)),
+            _ => Err(format!("expected true or false but found \"{}\"", value)),
+        },
+        (Kind::Choice(choices), RawValue::Text(value)) => {
+            if choices.contains(&value.as_str()) {
+                Ok(Value::Text(value.to_string()))
+            } else {
+                Err(format!(
+                    "unknown value \"{}\", expected one of {}",
+                    value,
+                    choices
+                        .iter()
+                        .map(|choice| format!("`{}`", choice))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ))
+            }
+        }
+        (_, RawValue::Text(value)) if value.starts_with('-') => {
+            Err("must not be negative".to_string())
+        }
+        (_, RawValue::Integer(value)) if *value < 0 => Err("must not be negative".to_string()),
+        (Kind::Goal, _) => {
+            let value = match value {
+                RawValue::Text(value) => value.to_string(),
+                RawValue::Integer(value) => value.to_string(),
+                _ => return Err("expected a number of pomodoros or a duration".to_string()),
+            };
+
+            goal::parse(&value)?;
+            Ok(Value::Text(value))
+        }
+        (Kind::Duration, _) | (Kind::Seconds, _) => {
+            let seconds = match value {
+                RawValue::Text(value) => duration::parse_seconds(value)?,
+                RawValue::Integer(value) => *value as u64,
+                _ => return Err("expected a duration".to_string()),
+            };
+
+            if seconds == 0 && kind == Kind::Duration {
+                return Err("must be greater than 0".to_string());
+            }
+
+            Ok(Value::Int(seconds))
+        }
+        (Kind::Count, _) => {
+            let number = match value {
+                RawValue::Text(value) => value
+                    .parse::<u64>()
+                    .map_err(|_| format!("expected a whole number but found \"{}\"", value))?,
+                RawValue::Integer(value) => *value as u64,
+                _ => return Err("expected a whole number".to_string()),
+            };
+
+            if number == 0 {
+                return Err("must be greater than 0".to_string());
+            }
+
+            Ok(Value::Int(number))
+        }
+        (Kind::Percent, _) => {
+            let percent = match value {
+                RawValue::Text(value) => value
+                    .trim_end_matches('%')
+                    .parse::<u64>()
+                    .map_err(|_| format!("expected a percentage but found \"{}\"", value))?,
+                RawValue::Integer(value) => *value as u64,
+                _ => return Err("expected a percentage".to_string()),
+            };
+
+            if percent > 100 {
+                return Err("must be at most 100%".to_string());
+            }
+
+            Ok(Value::Int(percent))
+        }
+        (Kind::Text, _) | (Kind::Colour, _) | (Kind::Choice(_), _) => {
+            Err("expected a string".to_string())
+        }
+        (Kind::Keys, _) => Err("expected a key or a list of keys".to_string()),
+        (Kind::Urls, _) => Err("expected a URL or a list of URLs".to_string()),
+        (Kind::Phases, _) => Err("expected a phase or a list of phases".to_string()),
+        (Kind::Bool, _) => Err("expected true or false".to_string()),
+    }
+}
+
+/// Checks the part of a key that matched the `*` of its setting
+fn check_wildcard(prefix: &str, rest: &str) -> Result<(), String> {
+    match prefix {
+        "keybindings.seek." => keymap::parse_seek(rest).map(|_| ()),
+        "webhook_headers." => {
+            if rest
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c))
+            {
+                Ok(())
+            } else {
+                Err(format!("\"{}\" isn't a valid header name", rest))
+            }
+        }
+        "hook_timeout." => {
+            if rest == "default" || HOOKS.iter().any(|hook| hook.name() == rest) {
+                Ok(())
+            } else {
+                Err(format!("there is no \"{}\" event", rest))
+            }
+        }
+        "phase." => check_phase_name(rest),
+        _ => Ok(()),
+    }
+}
+
+/// Phase names are made up of letters, numbers, `_` and `-`
+fn check_phase_name(name: &str) -> Result<(), String> {
+    if !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+    {
+        Ok(())
+    } else {
+        Err(format!(
+            "\"{}\" isn't a valid phase name, use letters, numbers, `_` and `-`",
+            name
+        ))
+    }
+}
+
+fn parse_phases(names: &[String]) -> Result<Value, String> {
+    for name in names {
+        check_phase_name(name)?;
+    }
+
+    Ok(Value::List(names.to_vec()))
+}
+
+/// Only the scheme is checked, anything else wrong with a URL comes up when it is used
+fn parse_urls(urls: &[String]) -> Result<Value, String> {
+    for url in urls {
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return Err(format!(
+                "\"{}\" isn't a URL, expected it to start with `http://` or `https://`",
+                url
+            ));
+        }
+    }
+
+    Ok(Value::List(urls.to_vec()))
+}
+
+/// Checks every key is valid, an empty list unbinds the action
+fn parse_keys(keys: &[String]) -> Result<Value, String> {
+    for key in keys {
+        keymap::parse_key(key)?;
+    }
+
+    Ok(Value::List(keys.to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every diagnostic for the config in the `key=value` format, formatted like `config check`
+    fn check(s: &str) -> Vec<String> {
+        let diagnostics = match Config::new_from_config_string(s) {
+            Ok(config) => config.warnings().to_vec(),
+            Err(error) => error.diagnostics,
+        };
+
+        diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn diagnostics_point_at_the_key_or_the_value() {
+        let s = "# Timings\n\
+                 work_time = 1500\n\
+                 \x20 nap_time=5\n\
+                 short_break_time =  -5\n\
+                 \tpomodoros_before_long_break=0\n\
+                 work_time=1800\n\
+                 just some words\n\
+                 = 5\n";
+
+        assert_eq!(
+            check(s),
+            [
+                "3:3: warning: unknown key `nap_time` (ignored)",
+                "4:21: error: invalid value for `short_break_time`: must not be negative",
+                "5:30: error: invalid value for `pomodoros_before_long_break`: must be greater than 0",
+                "6:1: warning: `work_time` was already set on line 2, this value replaces it",
+                "7:1: error: expected `key=value`",
+                "8:1: error: missing key",
+            ]
+        );
+    }
+
+    #[test]
+    fn warnings_dont_stop_the_config_loading() {
+        let config = Config::new_from_config_string("work_time=1500\nnap_time=5\n").unwrap();
+
+        assert_eq!(config.get_int("work_time"), Some(1500));
+        assert_eq!(config.get_int("nap_time"), None);
+        assert_eq!(
+            config.warnings(),
+            [Diagnostic {
+                line: 2,
+                column: 1,
+                severity: Severity::Warning,
+                message: "unknown key `nap_time` (ignored)".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn values_can_contain_equals() {
+        let config =
+            Config::new_from_config_string("on_work_start = notify-send \"a=b\" x==y \n").unwrap();
+
+        assert_eq!(
+            config.get_string("on_work_start").map(String::as_str),
+            Some("notify-send \"a=b\" x==y")
+        );
+    }
+
+    #[test]
+    fn numbers_must_be_in_range() {
+        let config = Config::new_from_config_string("resume_threshold=0\n").unwrap();
+        assert_eq!(config.get_int("resume_threshold"), Some(0));
+
+        assert_eq!(
+            check("work_time=0\nresume_threshold=-1\npomodoros_before_long_break=18446744073709551616\n"),
+            [
+                "1:11: error: invalid value for `work_time`: must be greater than 0",
+                "2:18: error: invalid value for `resume_threshold`: must not be negative",
+                "3:29: error: invalid value for `pomodoros_before_long_break`: expected a whole number but found \"18446744073709551616\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn overrides_are_checked_like_the_file() {
+        let mut config =
+            Config::new_from_config_string("work_time=25m\n[profile.deep]\nwork_time=50m\n")
+                .unwrap();
+
+        assert_eq!(
+            config.set("work_time", "0"),
+            Err("must be greater than 0".to_string())
+        );
+        assert_eq!(
+            config.set("nap_time", "5m"),
+            Err("unknown key `nap_time`".to_string())
+        );
+        assert_eq!(
+            config.set("hook_timeout.nap", "5s"),
+            Err("invalid key `hook_timeout.nap`: there is no \"nap\" event".to_string())
+        );
+
+        // They outrank every profile, including one switched to later
+        config.set("work_time", "40m").unwrap();
+        assert_eq!(config.get_int("work_time"), Some(2400));
+        config.set_profile("deep").unwrap();
+        assert_eq!(config.get_int("work_time"), Some(2400));
+    }
+
+    #[test]
+    fn values_must_be_the_right_type() {
+        assert_eq!(
+            check("pomodoros_before_long_break=four\non_work_start=\n"),
+            [
+                "1:29: error: invalid value for `pomodoros_before_long_break`: expected a whole number but found \"four\"",
+                "2:15: error: invalid value for `on_work_start`: expected a value",
+            ]
+        );
     }
 }
@@ -0,0 +1,238 @@
+//! Reads `tomato.toml` into the same entries as the `key=value` format.
+//!
+//! Top level keys are the same as in the `key=value` format. Keys in `[hooks]` are the hook
+//! name without the `on_` prefix (`work_start = "..."` is `on_work_start`), each table in
+//! `[profile]` is a profile and any other table is flattened into dotted keys (`[theme]` with
+//! `foreground = "blue"` is `theme.foreground`).
+
+use super::{check_profile_name, Diagnostic, Entry, RawValue, Severity};
+
+use std::ops::Range;
+
+use toml::de::{DeTable, DeValue};
+use toml::Spanned;
+
+/// Turns the file into entries, any problems with the TOML itself are returned as diagnostics
+pub(super) fn parse(s: &str) -> (Vec<Entry>, Vec<Diagnostic>) {
+    let (table, errors) = DeTable::parse_recoverable(s);
+
+    let mut reader = Reader {
+        s,
+        entries: Vec::new(),
+        diagnostics: Vec::new(),
+    };
+
+    for error in errors {
+        let span = error.span().unwrap_or(0..0);
+        reader.error(&span, error.message().to_string());
+    }
+
+    reader.read_table(table.get_ref(), None, "");
+
+    (reader.entries, reader.diagnostics)
+}
+
+struct Reader<'a> {
+    s: &'a str,
+    entries: Vec<Entry>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Reader<'_> {
+    /// Line and column of a byte offset into the file
+    fn position(&self, offset: usize) -> (usize, usize) {
+        let offset = offset.min(self.s.len());
+        let before = &self.s[..offset];
+        let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+
+        (
+            before.matches('\n').count() + 1,
+            before[line_start..].chars().count() + 1,
+        )
+    }
+
+    fn error(&mut self, span: &Range<usize>, message: String) {
+        let (line, column) = self.position(span.start);
+
+        self.diagnostics.push(Diagnostic {
+            line,
+            column,
+            severity: Severity::Error,
+            message,
+        });
+    }
+
+    /// `prefix` is the dotted name of the table (empty at the top level or in a profile)
+    fn read_table(&mut self, table: &DeTable, profile: Option<&str>, prefix: &str) {
+        for (key, value) in table {
+            let name = format!("{}{}", prefix, key.get_ref());
+
+            match (name.as_str(), value.get_ref()) {
+                ("profile", DeValue::Table(profiles)) if profile.is_none() => {
+                    self.read_profiles(profiles)
+                }
+                ("hooks", DeValue::Table(hooks)) => self.read_hooks(hooks, profile),
+                (_, DeValue::Table(table)) => {
+                    self.read_table(table, profile, &format!("{}.", name))
+                }
+                _ => self.read_value(key, value, profile, name),
+            }
+        }
+    }
+
+    fn read_profiles(&mut self, profiles: &DeTable) {
+        for (name, profile) in profiles {
+            if let Err(message) = check_profile_name(name.get_ref()) {
+                self.error(&name.span(), message);
+                continue;
+            }
+
+            match profile.get_ref() {
+                DeValue::Table(table) => self.read_table(table, Some(name.get_ref()), ""),
+                _ => self.error(
+                    &profile.span(),
+                    format!("expected `profile.{}` to be a table", name.get_ref()),
+                ),
+            }
+        }
+    }
+
+    fn read_hooks(&mut self, hooks: &DeTable, profile: Option<&str>) {
+        for (key, value) in hooks {
+            let name = format!("on_{}", key.get_ref());
+            self.read_value(key, value, profile, name);
+        }
+    }
+
+    fn read_value(
+        &mut self,
+        key: &Spanned<std::borrow::Cow<str>>,
+        value: &Spanned<DeValue>,
+        profile: Option<&str>,
+        name: String,
+    ) {
+        let raw_value = match to_raw_value(value) {
+            Ok(raw_value) => raw_value,
+            Err((span, message)) => {
+                self.error(&span, format!("invalid value for `{}`: {}", name, message));
+                return;
+            }
+        };
+
+        self.entries.push(Entry {
+            profile: profile.map(str::to_string),
+            key: name,
+            value: raw_value,
+            position: self.position(key.span().start),
+            value_position: self.position(value.span().start),
+        });
+    }
+}
+
+fn to_raw_value(value: &Spanned<DeValue>) -> Result<RawValue, (Range<usize>, String)> {
+    match value.get_ref() {
+        DeValue::String(s) => Ok(RawValue::Text(s.to_string())),
+        DeValue::Integer(integer) => i64::from_str_radix(integer.as_str(), integer.radix())
+            .map(RawValue::Integer)
+            .map_err(|_| (value.span(), "number is too large".to_string())),
+        DeValue::Boolean(boolean) => Ok(RawValue::Boolean(*boolean)),
+        DeValue::Array(array) => array
+            .iter()
+            .map(|item| match item.get_ref() {
+                DeValue::String(s) => Ok(s.to_string()),
+                _ => Err((item.span(), "lists may only contain strings".to_string())),
+            })
+            .collect::<Result<Vec<String>, _>>()
+            .map(RawValue::List),
+        other => Err((
+            value.span(),
+            format!("{} values aren't supported", other.type_str()),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+
+    /// Every diagnostic for the TOML config, formatted like `config check`
+    fn check(s: &str) -> Vec<String> {
+        let diagnostics = match Config::new_from_toml_string(s) {
+            Ok(config) => config.warnings().to_vec(),
+            Err(error) => error.diagnostics,
+        };
+
+        diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn tables_become_keys() {
+        let config = Config::new_from_toml_string(
+            "work_time = \"50m\"\n\
+             \n\
+             [hooks]\n\
+             work_start = \"echo start\"\n\
+             \n\
+             [theme]\n\
+             foreground = \"blue\"\n\
+             \n\
+             [profile.short]\n\
+             work_time = 900\n",
+        )
+        .unwrap();
+
+        assert_eq!(config.get_int("work_time"), Some(3000));
+        assert_eq!(
+            config.get_string("on_work_start").map(String::as_str),
+            Some("echo start")
+        );
+        assert_eq!(
+            config.get_string("theme.foreground").map(String::as_str),
+            Some("blue")
+        );
+        assert_eq!(
+            config.with_profile("short").unwrap().get_int("work_time"),
+            Some(900)
+        );
+    }
+
+    #[test]
+    fn values_must_be_the_right_type() {
+        assert_eq!(
+            check(
+                "work_time = true\n\
+                 pomodoros_before_long_break = \"four\"\n\
+                 on_work_start = 1\n\
+                 short_break_time = 1.5\n\
+                 history_file = [\"a\", 3]\n\
+                 long_break_time = -5\n\
+                 resume_threshold = 99999999999999999999\n\
+                 nap_time = 5\n"
+            ),
+            [
+                "1:13: error: invalid value for `work_time`: expected a duration",
+                "2:31: error: invalid value for `pomodoros_before_long_break`: expected a whole number but found \"four\"",
+                "3:17: error: invalid value for `on_work_start`: expected a string",
+                "4:20: error: invalid value for `short_break_time`: float values aren't supported",
+                "5:22: error: invalid value for `history_file`: lists may only contain strings",
+                "6:19: error: invalid value for `long_break_time`: must not be negative",
+                "7:20: error: invalid value for `resume_threshold`: number is too large",
+                "8:1: warning: unknown key `nap_time` (ignored)",
+            ]
+        );
+    }
+
+    #[test]
+    fn syntax_errors_have_a_position() {
+        assert_eq!(
+            check("work_time = \"25m\"\nshort_break_time = \n"),
+            [
+                "2:20: error: string values must be quoted, expected literal string",
+                "2:20: error: invalid value for `short_break_time`: expected a duration",
+            ]
+        );
+    }
+}
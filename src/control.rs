@@ -0,0 +1,187 @@
+use crate::config::Config;
+use crate::event::Event;
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::Shutdown;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::thread;
+
+/// Commands that can be sent to the daemon, one per line
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    Pause,
+    Skip,
+    Prev,
+    Long,
+    /// Sets the label (the rest of the line), clears it if there is nothing after the command
+    Label,
+    Status,
+    /// Keeps the connection open and sends the status on every tick
+    Watch,
+    Stop,
+}
+
+impl Command {
+    pub fn from_str(s: &str) -> Option<Command> {
+        match s {
+            "pause" => Some(Command::Pause),
+            "skip" => Some(Command::Skip),
+            "prev" => Some(Command::Prev),
+            "long" => Some(Command::Long),
+            "label" => Some(Command::Label),
+            "status" => Some(Command::Status),
+            "watch" => Some(Command::Watch),
+            "stop" => Some(Command::Stop),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Command::Pause => "pause",
+            Command::Skip => "skip",
+            Command::Prev => "prev",
+            Command::Long => "long",
+            Command::Label => "label",
+            Command::Status => "status",
+            Command::Watch => "watch",
+            Command::Stop => "stop",
+        }
+    }
+}
+
+/// A command from a client that is waiting for a reply
+pub struct Request {
+    pub command: Command,
+    /// Everything on the line after the command
+    pub argument: Option<String>,
+    stream: UnixStream,
+}
+
+impl Request {
+    pub fn respond(mut self, reply: &str) {
+        // The client may have already gone away, there's nobody to tell if this fails
+        let _ = self.send_line(reply);
+    }
+
+    /// Sends a line without closing the connection (used for watching)
+    pub fn send_line(&mut self, line: &str) -> io::Result<()> {
+        writeln!(self.stream, "{}", line)
+    }
+
+    /// Makes `send_line` fail with `WouldBlock` instead of waiting when the client isn't reading
+    /// what it has been sent
+    pub fn set_nonblocking(&self) -> io::Result<()> {
+        self.stream.set_nonblocking(true)
+    }
+}
+
+/// Uses `socket_file` from the config if set, otherwise `$XDG_RUNTIME_DIR/tomato.sock` falling
+/// back to the temp directory
+pub fn socket_path(config: &Config) -> PathBuf {
+    config
+        .get_string("socket_file")
+        .map(PathBuf::from)
+        .or_else(|| {
+            dirs::runtime_dir().map(|mut path| {
+                path.push("tomato.sock");
+                path
+            })
+        })
+        .unwrap_or_else(|| {
+            let mut path = std::env::temp_dir();
+            path.push(format!(
+                "tomato-{}.sock",
+                std::env::var("USER").unwrap_or_default()
+            ));
+            path
+        })
+}
+
+/// Starts listening on the socket, every command that is received gets sent as an input event
+pub fn listen(path: &Path, tx: Sender<Event<Request>>) -> io::Result<()> {
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("A daemon is already listening on {}", path.display()),
+            ));
+        }
+
+        // Nothing is listening so this must be left over from a daemon that didn't shut down
+        std::fs::remove_file(path)?;
+    }
+
+    let listener = UnixListener::bind(path)?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+
+            let tx = tx.clone();
+            thread::spawn(move || handle_connection(stream, tx));
+        }
+    });
+
+    Ok(())
+}
+
+fn handle_connection(mut stream: UnixStream, tx: Sender<Event<Request>>) {
+    let mut line = String::new();
+
+    if BufReader::new(&stream).read_line(&mut line).is_err() {
+        return;
+    }
+
+    let line = line.trim();
+    let (name, argument) = match line.find(char::is_whitespace) {
+        Some(i) => (&line[..i], Some(line[i..].trim().to_string())),
+        None => (line, None),
+    };
+
+    match Command::from_str(name) {
+        Some(command) => {
+            // If the daemon has stopped then the request is dropped which closes the stream
+            let _ = tx.send(Event::Input(Request {
+                command,
+                argument,
+                stream,
+            }));
+        }
+        None => {
+            let _ = writeln!(stream, "error: unknown command \"{}\"", name);
+        }
+    }
+}
+
+/// Sends the command (and its argument) to the daemon, the reply is one line per status update
+/// (only one unless watching)
+pub fn send(
+    path: &Path,
+    command: Command,
+    argument: Option<&str>,
+) -> io::Result<io::Lines<BufReader<UnixStream>>> {
+    let mut stream = UnixStream::connect(path).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!(
+                "Couldn't connect to the daemon at {} ({}), is `tomato daemon` running?",
+                path.display(),
+                e
+            ),
+        )
+    })?;
+
+    match argument {
+        Some(argument) => writeln!(stream, "{} {}", command.as_str(), argument)?,
+        None => writeln!(stream, "{}", command.as_str())?,
+    }
+    stream.shutdown(Shutdown::Write)?;
+
+    Ok(BufReader::new(stream).lines())
+}
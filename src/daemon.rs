@@ -0,0 +1,110 @@
+use crate::app::App;
+use crate::config::Config;
+use crate::control::{self, Command, Request};
+use crate::event::{self, Event};
+use crate::hooks::Hook;
+use crate::input::InputManager;
+use crate::keymap::Action;
+use crate::notify::Notifier;
+use crate::sound::Sounds;
+use crate::status::Status;
+use crate::tasks::TaskList;
+
+use std::sync::mpsc;
+use std::sync::Arc;
+
+/// Runs the timer without a terminal, it is controlled through the socket instead
+pub fn run(config: Arc<Config>, mut app: App) -> Result<(), failure::Error> {
+    let socket_path = control::socket_path(&config);
+
+    let (tx, rx) = mpsc::channel();
+    control::listen(&socket_path, tx.clone())?;
+    event::spawn_ticker(tx.clone(), 400);
+
+    let mut input_manager = InputManager::new(Arc::clone(&config));
+
+    match Notifier::from_config(&config, move |action| {
+        let _ = tx.send(Event::Action(action));
+    }) {
+        Ok(notifier) => input_manager.set_notifier(notifier),
+        Err(e) => eprintln!("Couldn't show notifications: {}", e),
+    }
+
+    match Sounds::from_config(&config) {
+        Ok(sounds) => input_manager.set_sounds(sounds),
+        Err(e) => eprintln!("Couldn't play sounds: {}", e),
+    }
+
+    match TaskList::from_config(&config) {
+        Ok(tasks) => app.set_tasks(tasks),
+        Err(e) => eprintln!("Couldn't load the tasks: {}", e),
+    }
+    let mut watchers: Vec<Request> = Vec::new();
+
+    loop {
+        match rx.recv()? {
+            Event::Input(request) => {
+                if request.command == Command::Watch {
+                    // A client that stops reading (like a suspended status bar) mustn't hold up
+                    // the timer, it is dropped once the socket's buffer is full
+                    if request.set_nonblocking().is_ok() {
+                        watchers.push(request);
+                    }
+                } else if !handle_request(request, &mut input_manager, &mut app) {
+                    break;
+                }
+            }
+            Event::Tick => {
+                input_manager.tick(&mut app);
+
+                let line = Status::from_app(&app).to_line();
+                // Stop watching once the client goes away or falls behind
+                watchers.retain_mut(|watcher| watcher.send_line(&line).is_ok());
+            }
+            Event::Action(action) => {
+                if !input_manager.perform(action, &mut app) {
+                    break;
+                }
+
+                input_manager.tick(&mut app);
+            }
+        }
+    }
+
+    app.save_snapshot();
+    app.fire(Hook::Quit, Vec::new());
+    input_manager.run_hooks(&mut app);
+    input_manager.finish_hooks();
+    std::fs::remove_file(&socket_path)?;
+
+    Ok(())
+}
+
+/// Returns false when the daemon should stop
+fn handle_request(request: Request, input_manager: &mut InputManager, app: &mut App) -> bool {
+    let action = match request.command {
+        Command::Pause => Some(Action::TogglePause),
+        Command::Skip => Some(Action::Next),
+        Command::Prev => Some(Action::Prev),
+        Command::Long => Some(Action::Elongate),
+        Command::Label => {
+            app.set_label(request.argument.clone());
+            None
+        }
+        Command::Status | Command::Watch => None,
+        Command::Stop => {
+            request.respond("stopping");
+            return false;
+        }
+    };
+
+    if let Some(action) = action {
+        input_manager.perform(action, app);
+    }
+
+    // Commands that change the state need the progress data to be up to date for the status
+    input_manager.tick(app);
+    request.respond(&Status::from_app(app).to_line());
+
+    true
+}
@@ -0,0 +1,137 @@
+/// Parses a human friendly duration into seconds.
+///
+/// Accepts a plain number of seconds (`90`), units (`25m`, `1h30m`, `90s`, `1h 5m 30s`) or clock
+/// style `m:ss` / `h:mm:ss` (`1:30` is one and a half minutes).
+pub fn parse_seconds(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+
+    if s.is_empty() {
+        return Err("expected a duration".to_string());
+    }
+
+    if let Ok(seconds) = s.parse::<u64>() {
+        return Ok(seconds);
+    }
+
+    if s.contains(':') {
+        return parse_clock(s);
+    }
+
+    parse_units(s)
+}
+
+/// `m:ss` or `h:mm:ss`
+fn parse_clock(s: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid duration \"{}\", expected m:ss or h:mm:ss", s);
+
+    let parts = s
+        .split(':')
+        .map(|part| part.parse::<u64>().map_err(|_| invalid()))
+        .collect::<Result<Vec<u64>, String>>()?;
+
+    // Too many hours or minutes to count in seconds is as invalid as a malformed time
+    match parts.as_slice() {
+        [minutes, seconds] if *seconds < 60 => minutes
+            .checked_mul(60)
+            .and_then(|total| total.checked_add(*seconds))
+            .ok_or_else(invalid),
+        [hours, minutes, seconds] if *minutes < 60 && *seconds < 60 => hours
+            .checked_mul(3600)
+            .and_then(|total| total.checked_add(minutes * 60 + seconds))
+            .ok_or_else(invalid),
+        _ => Err(invalid()),
+    }
+}
+
+/// A sequence of numbers each followed by `h`, `m` or `s`
+fn parse_units(s: &str) -> Result<u64, String> {
+    let invalid = || {
+        format!(
+            "invalid duration \"{}\", expected something like 25m or 1h30m",
+            s
+        )
+    };
+
+    let mut total: u64 = 0;
+    let mut number = String::new();
+
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+
+        if c.is_whitespace() && number.is_empty() {
+            continue;
+        }
+
+        let multiplier = match c {
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(invalid()),
+        };
+
+        let value = number.parse::<u64>().map_err(|_| invalid())?;
+        total = value
+            .checked_mul(multiplier)
+            .and_then(|seconds| total.checked_add(seconds))
+            .ok_or_else(invalid)?;
+        number.clear();
+    }
+
+    // Every number must have a unit
+    if !number.is_empty() {
+        return Err(invalid());
+    }
+
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_in_every_style() {
+        for (s, seconds) in [
+            ("90", 90),
+            (" 25m ", 1500),
+            ("1h30m", 5400),
+            ("1h 5m 30s", 3930),
+            ("90s", 90),
+            ("0m", 0),
+            ("1:30", 90),
+            ("1:05:00", 3900),
+            ("0:00", 0),
+        ] {
+            assert_eq!(parse_seconds(s), Ok(seconds), "{}", s);
+        }
+    }
+
+    #[test]
+    fn malformed_durations_are_rejected() {
+        for s in [
+            "", "  ", "25 m", "m", "25x", "1h30", "-5m", "1.5h", "h30m", "1:60", "1:2:3:4",
+            "1::00", ":30", "1:-5",
+        ] {
+            assert!(parse_seconds(s).is_err(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn durations_too_long_to_count_are_rejected() {
+        assert_eq!(parse_seconds("18446744073709551615"), Ok(u64::MAX));
+
+        for s in [
+            "18446744073709551616",
+            "5124095576030432h",
+            // Each part fits, the total doesn't
+            "307445734561825860m 16s",
+            "307445734561825860:16",
+            "5124095576030432:00:00",
+        ] {
+            assert!(parse_seconds(s).is_err(), "{:?}", s);
+        }
+    }
+}
@@ -3,17 +3,22 @@ use std::sync::mpsc;
 use std::thread;
 use std::time::Duration;
 
-use crossterm::event::{read, Event as TerminalEvent, KeyCode as Key};
+use crossterm::event::{read, Event as TerminalEvent, KeyEvent};
+
+use crate::keymap::Action;
 
 pub enum Event<I> {
     Input(I),
     Tick,
+    /// An action from outside of the terminal (like a button on a notification)
+    Action(Action),
 }
 
 /// An small event handler that wraps termion input and tick events. Each event
 /// type is handled in its own thread.
 pub struct Events {
-    rx: mpsc::Receiver<Event<Key>>,
+    rx: mpsc::Receiver<Event<KeyEvent>>,
+    tx: mpsc::Sender<Event<KeyEvent>>,
 }
 
 impl Events {
@@ -26,30 +31,37 @@ impl Events {
             while let Ok(event) = read() {
                 if let TerminalEvent::Key(key) = event {
                     // Will stop this thread if the main thread has dropped it's receiver
-                    if key_tx.send(Event::Input(key.code)).is_err() {
+                    if key_tx.send(Event::Input(key)).is_err() {
                         return;
                     }
                 }
             }
         });
 
-        let tick_rate = Duration::from_millis(tick_rate_millis);
-
-        thread::spawn(move || {
-            let tx = tx.clone();
-            loop {
-                if tx.send(Event::Tick).is_err() {
-                    return;
-                }
+        spawn_ticker(tx.clone(), tick_rate_millis);
 
-                thread::sleep(tick_rate);
-            }
-        });
+        Events { rx, tx }
+    }
 
-        Events { rx }
+    /// For sending events from other threads
+    pub fn sender(&self) -> mpsc::Sender<Event<KeyEvent>> {
+        self.tx.clone()
     }
 
-    pub fn next(&self) -> Result<Event<Key>, mpsc::RecvError> {
+    pub fn next(&self) -> Result<Event<KeyEvent>, mpsc::RecvError> {
         self.rx.recv()
     }
 }
+
+/// Sends a tick event every `tick_rate_millis` until the receiver is dropped
+pub fn spawn_ticker<I: Send + 'static>(tx: mpsc::Sender<Event<I>>, tick_rate_millis: u64) {
+    let tick_rate = Duration::from_millis(tick_rate_millis);
+
+    thread::spawn(move || loop {
+        if tx.send(Event::Tick).is_err() {
+            return;
+        }
+
+        thread::sleep(tick_rate);
+    });
+}
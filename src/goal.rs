@@ -0,0 +1,114 @@
+use crate::config::Config;
+use crate::duration;
+use crate::stats::format_duration;
+
+use std::time::Duration;
+
+/// How much work to get done each day
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Goal {
+    Pomodoros(u64),
+    WorkTime(Duration),
+}
+
+/// Work done so far in a day
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Progress {
+    pub pomodoros: u64,
+    pub work_time: Duration,
+}
+
+impl Goal {
+    /// Uses `daily_goal` from the config, there is no goal if it isn't set
+    pub fn from_config(config: &Config) -> Option<Goal> {
+        config
+            .get_string("daily_goal")
+            .and_then(|goal| parse(goal).ok())
+    }
+
+    /// How much of the goal is done, between 0 and 1
+    pub fn ratio(self, progress: &Progress) -> f64 {
+        let ratio = match self {
+            Goal::Pomodoros(pomodoros) => progress.pomodoros as f64 / pomodoros as f64,
+            Goal::WorkTime(work_time) => progress.work_time.as_secs_f64() / work_time.as_secs_f64(),
+        };
+
+        ratio.min(1.0)
+    }
+
+    pub fn is_reached(self, progress: &Progress) -> bool {
+        match self {
+            Goal::Pomodoros(pomodoros) => progress.pomodoros >= pomodoros,
+            Goal::WorkTime(work_time) => progress.work_time >= work_time,
+        }
+    }
+
+    /// `3/8 pomodoros` or `2h10m/4h00m of work`
+    pub fn describe(self, progress: &Progress) -> String {
+        match self {
+            Goal::Pomodoros(pomodoros) => format!("{}/{} pomodoros", progress.pomodoros, pomodoros),
+            Goal::WorkTime(work_time) => format!(
+                "{}/{} of work",
+                format_duration(progress.work_time),
+                format_duration(work_time)
+            ),
+        }
+    }
+}
+
+/// A plain number is a number of pomodoros (`8`), a duration is an amount of work (`4h`, `3h30m`)
+pub fn parse(s: &str) -> Result<Goal, String> {
+    let s = s.trim();
+
+    if let Ok(pomodoros) = s.parse::<u64>() {
+        return match pomodoros {
+            0 => Err("the goal must be greater than 0".to_string()),
+            pomodoros => Ok(Goal::Pomodoros(pomodoros)),
+        };
+    }
+
+    match duration::parse_seconds(s)? {
+        0 => Err("the goal must be greater than 0".to_string()),
+        seconds => Ok(Goal::WorkTime(Duration::from_secs(seconds))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn goals_are_pomodoros_or_work_time() {
+        assert_eq!(parse("8"), Ok(Goal::Pomodoros(8)));
+        assert_eq!(
+            parse(" 4h "),
+            Ok(Goal::WorkTime(Duration::from_secs(4 * 3600)))
+        );
+        assert_eq!(
+            parse("3h30m"),
+            Ok(Goal::WorkTime(Duration::from_secs(12600)))
+        );
+
+        for s in ["0", "0m", "", "-3", "lots"] {
+            assert!(parse(s).is_err(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn progress_is_measured_against_the_goal() {
+        let progress = Progress {
+            pomodoros: 3,
+            work_time: Duration::from_secs(7800),
+        };
+
+        let pomodoros = Goal::Pomodoros(4);
+        assert_eq!(pomodoros.ratio(&progress), 0.75);
+        assert!(!pomodoros.is_reached(&progress));
+        assert_eq!(pomodoros.describe(&progress), "3/4 pomodoros");
+
+        let work_time = Goal::WorkTime(Duration::from_secs(7200));
+        assert_eq!(work_time.ratio(&progress), 1.0);
+        assert!(work_time.is_reached(&progress));
+        assert_eq!(work_time.describe(&progress), "2h10m/2h00m of work");
+    }
+}
@@ -0,0 +1,173 @@
+use crate::app::AppState;
+use crate::config::Config;
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How a phase came to an end
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PhaseOutcome {
+    /// The timer ran out
+    Completed,
+    /// The user skipped to the next state before the timer ran out
+    Skipped,
+    /// The user went back to the previous state
+    Rewound,
+    /// A short break that was turned into a long break
+    Elongated,
+}
+
+impl PhaseOutcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PhaseOutcome::Completed => "completed",
+            PhaseOutcome::Skipped => "skipped",
+            PhaseOutcome::Rewound => "rewound",
+            PhaseOutcome::Elongated => "elongated",
+        }
+    }
+
+    fn from_str(s: &str) -> Option<PhaseOutcome> {
+        match s {
+            "completed" => Some(PhaseOutcome::Completed),
+            "skipped" => Some(PhaseOutcome::Skipped),
+            "rewound" => Some(PhaseOutcome::Rewound),
+            "elongated" => Some(PhaseOutcome::Elongated),
+            _ => None,
+        }
+    }
+}
+
+/// A single finished phase (work session or break)
+#[derive(Clone, Debug)]
+pub struct PhaseRecord {
+    pub state: AppState,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    /// The length the phase was set to when it ended
+    pub planned: Duration,
+    /// How much of the phase actually ran, more than `planned` only for a work session that was
+    /// completed in overtime
+    pub actual: Duration,
+    pub outcome: PhaseOutcome,
+    /// The label that was set when the phase ended
+    pub label: Option<String>,
+    /// Name of the phase in the cycle (missing from older records)
+    pub phase: Option<String>,
+    /// Whether going back to the previous phase took a pomodoro away, only set for rewound
+    /// phases (and missing from older records)
+    pub took_back: Option<bool>,
+}
+
+impl PhaseRecord {
+    /// Each record is a single tab separated line:
+    /// `start end state planned actual outcome label phase took_back` where times are unix seconds
+    /// and durations are in seconds. The label is empty if there wasn't one (and missing from older
+    /// records, like the phase). `took_back` is `1` or `0` for rewound phases and empty otherwise.
+    fn to_line(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            unix_seconds(self.start),
+            unix_seconds(self.end),
+            self.state.key(),
+            self.planned.as_secs(),
+            self.actual.as_secs(),
+            self.outcome.as_str(),
+            self.label.as_deref().unwrap_or(""),
+            self.phase.as_deref().unwrap_or(""),
+            match self.took_back {
+                Some(true) => "1",
+                Some(false) => "0",
+                None => "",
+            }
+        )
+    }
+
+    fn from_line(line: &str) -> Option<PhaseRecord> {
+        let parts = line.split('\t').collect::<Vec<&str>>();
+
+        if parts.len() < 6 {
+            return None;
+        }
+
+        Some(PhaseRecord {
+            start: UNIX_EPOCH + Duration::from_secs(parts[0].parse().ok()?),
+            end: UNIX_EPOCH + Duration::from_secs(parts[1].parse().ok()?),
+            state: AppState::from_key(parts[2])?,
+            planned: Duration::from_secs(parts[3].parse().ok()?),
+            actual: Duration::from_secs(parts[4].parse().ok()?),
+            outcome: PhaseOutcome::from_str(parts[5])?,
+            label: parts
+                .get(6)
+                .filter(|label| !label.is_empty())
+                .map(|label| label.to_string()),
+            phase: parts
+                .get(7)
+                .filter(|phase| !phase.is_empty())
+                .map(|phase| phase.to_string()),
+            took_back: match parts.get(8) {
+                Some(&"1") => Some(true),
+                Some(&"0") => Some(false),
+                _ => None,
+            },
+        })
+    }
+}
+
+pub fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// Append-only log of every finished phase
+pub struct History {
+    path: PathBuf,
+}
+
+impl History {
+    pub fn new(path: PathBuf) -> History {
+        History { path }
+    }
+
+    /// Uses `history_file` from the config if set, otherwise `$XDG_DATA_HOME/tomato/history.log`
+    pub fn from_config(config: &Config) -> Option<History> {
+        config
+            .get_string("history_file")
+            .map(PathBuf::from)
+            .or_else(|| {
+                dirs::data_dir().map(|mut path| {
+                    path.push("tomato/history.log");
+                    path
+                })
+            })
+            .map(History::new)
+    }
+
+    pub fn append(&self, record: &PhaseRecord) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+
+        writeln!(file, "{}", record.to_line())
+    }
+
+    /// Reads every record in the log, lines that can't be understood are skipped.
+    /// A log that doesn't exist yet is treated as empty.
+    pub fn read_records(&self) -> io::Result<Vec<PhaseRecord>> {
+        let s = match fs::read_to_string(&self.path) {
+            Ok(s) => s,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        Ok(s.lines().filter_map(PhaseRecord::from_line).collect())
+    }
+}
@@ -0,0 +1,593 @@
+use crate::config::Config;
+
+use chrono::{DateTime, Local};
+
+use std::collections::VecDeque;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Seconds a script can run for before it is killed, unless `hook_timeout` says otherwise
+const DEFAULT_TIMEOUT: u64 = 30;
+/// How often running scripts are checked on
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// The log file is rotated once it is bigger than this (1 MiB)
+const MAX_LOG_SIZE: u64 = 1024 * 1024;
+/// Entries kept in memory for the log pane
+const MAX_ENTRIES: usize = 50;
+
+/// Points in the life of the timer that a script can be run at (`on_NAME` in the config)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Hook {
+    /// The timer was started (or resumed from the session file)
+    Start,
+    /// The timer is about to exit
+    Quit,
+    WorkStart,
+    WorkEnd,
+    BreakStart,
+    BreakEnd,
+    Pause,
+    Resume,
+    /// Skipped to the next state before the timer ran out
+    Skip,
+    /// Went back to the previous state
+    Rewind,
+    /// A short break was turned into a long break
+    Elongate,
+    /// There is `time_left_warning` left of the current state
+    TimeLeft,
+    /// The daily goal was reached
+    GoalReached,
+    /// A work session ran out with `overtime` on, so it carries on until it is ended
+    Overtime,
+}
+
+pub const HOOKS: &[Hook] = &[
+    Hook::Start,
+    Hook::Quit,
+    Hook::WorkStart,
+    Hook::WorkEnd,
+    Hook::BreakStart,
+    Hook::BreakEnd,
+    Hook::Pause,
+    Hook::Resume,
+    Hook::Skip,
+    Hook::Rewind,
+    Hook::Elongate,
+    Hook::TimeLeft,
+    Hook::GoalReached,
+    Hook::Overtime,
+];
+
+impl Hook {
+    /// Name of the hook, the config key is this with `on_` in front
+    pub fn name(self) -> &'static str {
+        match self {
+            Hook::Start => "start",
+            Hook::Quit => "quit",
+            Hook::WorkStart => "work_start",
+            Hook::WorkEnd => "work_end",
+            Hook::BreakStart => "break_start",
+            Hook::BreakEnd => "break_end",
+            Hook::Pause => "pause",
+            Hook::Resume => "resume",
+            Hook::Skip => "skip",
+            Hook::Rewind => "rewind",
+            Hook::Elongate => "elongate",
+            Hook::TimeLeft => "time_left",
+            Hook::GoalReached => "goal_reached",
+            Hook::Overtime => "overtime",
+        }
+    }
+}
+
+/// The script for each hook and how long it may run for
+#[derive(Clone, Default)]
+pub struct Hooks {
+    /// Every hook, whether or not it has a script
+    scripts: Vec<(Hook, Option<String>, Duration)>,
+}
+
+impl Hooks {
+    /// `hook_timeout.EVENT` is the timeout for a single hook, `hook_timeout.default` for every
+    /// other one
+    pub fn from_config(config: &Config) -> Hooks {
+        let default_timeout = config
+            .get_int("hook_timeout.default")
+            .unwrap_or(DEFAULT_TIMEOUT);
+
+        Hooks {
+            scripts: HOOKS
+                .iter()
+                .map(|&hook| {
+                    let script = config.get_string(&format!("on_{}", hook.name()));
+                    let timeout = config
+                        .get_int(&format!("hook_timeout.{}", hook.name()))
+                        .unwrap_or(default_timeout);
+
+                    (hook, script.cloned(), Duration::from_secs(timeout))
+                })
+                .collect(),
+        }
+    }
+
+    /// The script and its timeout
+    pub fn get(&self, hook: Hook) -> Option<(&str, Duration)> {
+        self.scripts
+            .iter()
+            .find(|(other, _, _)| *other == hook)
+            .and_then(|(_, script, timeout)| Some((script.as_deref()?, *timeout)))
+    }
+
+    /// How long the script for the hook may run for, even if it doesn't have one
+    pub fn timeout(&self, hook: Hook) -> Duration {
+        self.scripts
+            .iter()
+            .find(|(other, _, _)| *other == hook)
+            .map_or(Duration::from_secs(DEFAULT_TIMEOUT), |(_, _, timeout)| {
+                *timeout
+            })
+    }
+}
+
+/// A hook that has been triggered and the env variables its script gets, whether or not it has a
+/// script (notifications and webhooks react to these too)
+#[derive(Clone, Debug)]
+pub struct Trigger {
+    pub hook: Hook,
+    pub env: Vec<(&'static str, String)>,
+}
+
+impl Trigger {
+    pub fn var(&self, key: &str) -> Option<&str> {
+        self.env
+            .iter()
+            .find(|(other, _)| *other == key)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// A hook that has been triggered, with the environment its script is run with
+#[derive(Clone, Debug)]
+pub struct Invocation {
+    pub hook: Hook,
+    pub script: String,
+    pub env: Vec<(&'static str, String)>,
+    /// The script is killed if it is still running after this long
+    pub timeout: Duration,
+}
+
+/// How a script finished
+#[derive(Clone, Debug, PartialEq)]
+pub enum Status {
+    Exited(i32),
+    /// Killed by a signal (that it didn't get from the timeout)
+    Killed,
+    TimedOut(Duration),
+    /// It couldn't be started (or waited for)
+    Failed(String),
+    /// A webhook for the hook couldn't be delivered
+    Undelivered(String),
+}
+
+impl Status {
+    pub fn is_failure(&self) -> bool {
+        *self != Status::Exited(0)
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Status::Exited(code) => format!("exited with {}", code),
+            Status::Killed => "was killed".to_string(),
+            Status::TimedOut(timeout) => format!("timed out after {}s", timeout.as_secs()),
+            Status::Failed(error) => format!("couldn't be run: {}", error),
+            Status::Undelivered(error) => format!("webhook failed: {}", error),
+        }
+    }
+}
+
+/// A script that has finished (or a webhook that couldn't be delivered)
+#[derive(Clone, Debug)]
+pub struct Entry {
+    /// When it was started
+    pub time: DateTime<Local>,
+    pub hook: Hook,
+    pub status: Status,
+    /// Everything it wrote to stdout followed by everything it wrote to stderr
+    pub output: Vec<String>,
+}
+
+impl Entry {
+    /// `12:30:00 work_start exited with 0`
+    pub fn summary(&self) -> String {
+        format!(
+            "{} {} {}",
+            self.time.format("%H:%M:%S"),
+            self.hook.name(),
+            self.status.describe()
+        )
+    }
+}
+
+/// The scripts that have finished recently, every entry is also appended to the log file (which
+/// is rotated once it gets too big)
+pub struct HookLog {
+    path: Option<PathBuf>,
+    entries: VecDeque<Entry>,
+    /// Scripts that haven't finished yet
+    running: usize,
+    /// Webhook payloads that haven't had their first attempt at being delivered yet
+    sending: usize,
+    /// Failures since `mark_seen` was last called
+    unseen_failures: usize,
+    /// Signalled whenever a script finishes or a webhook payload has had its first attempt
+    finished: Arc<Condvar>,
+}
+
+impl HookLog {
+    /// Uses `hook_log_file` from the config if set, otherwise `$XDG_DATA_HOME/tomato/hooks.log`
+    pub fn from_config(config: &Config) -> HookLog {
+        let path = config
+            .get_string("hook_log_file")
+            .map(PathBuf::from)
+            .or_else(|| {
+                dirs::data_dir().map(|mut path| {
+                    path.push("tomato/hooks.log");
+                    path
+                })
+            });
+
+        HookLog {
+            path,
+            entries: VecDeque::new(),
+            running: 0,
+            sending: 0,
+            unseen_failures: 0,
+            finished: Arc::new(Condvar::new()),
+        }
+    }
+
+    pub fn add(&mut self, entry: Entry) {
+        if let Some(path) = &self.path {
+            // Like the history the log is best-effort
+            let _ = append(path, &entry);
+        }
+
+        if entry.status.is_failure() {
+            self.unseen_failures += 1;
+        }
+
+        if self.entries.len() == MAX_ENTRIES {
+            self.entries.pop_front();
+        }
+
+        self.entries.push_back(entry);
+    }
+
+    /// Oldest first
+    pub fn entries(&self) -> impl DoubleEndedIterator<Item = &Entry> {
+        self.entries.iter()
+    }
+
+    pub fn running(&self) -> usize {
+        self.running
+    }
+
+    pub fn sending(&self) -> usize {
+        self.sending
+    }
+
+    /// Counts a webhook payload as being sent until `finish_sending`, so quitting can wait for it
+    pub fn start_sending(&mut self) {
+        self.sending += 1;
+    }
+
+    pub fn finish_sending(&mut self) {
+        self.sending -= 1;
+        self.finished.notify_all();
+    }
+
+    pub fn unseen_failures(&self) -> usize {
+        self.unseen_failures
+    }
+
+    pub fn mark_seen(&mut self) {
+        self.unseen_failures = 0;
+    }
+}
+
+/// Waits until every script has finished. Webhook payloads are waited for until they have had
+/// their first attempt at being sent, or until `sending_deadline` (they aren't waited for at all
+/// without one).
+pub fn wait_for_hooks(log: &Mutex<HookLog>, sending_deadline: Option<Instant>) {
+    let mut log = log.lock().unwrap();
+    let finished = Arc::clone(&log.finished);
+
+    loop {
+        let sending = match sending_deadline {
+            Some(deadline) if log.sending > 0 => deadline.checked_duration_since(Instant::now()),
+            _ => None,
+        };
+
+        log = match sending {
+            Some(left) => finished.wait_timeout(log, left).unwrap().0,
+            None if log.running > 0 => finished.wait(log).unwrap(),
+            None => break,
+        };
+    }
+}
+
+/// Appends the entry to the log, moving the log to `PATH.1` first if it is too big
+fn append(path: &Path, entry: &Entry) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    if fs::metadata(path)
+        .map(|metadata| metadata.len())
+        .unwrap_or(0)
+        > MAX_LOG_SIZE
+    {
+        let mut rotated = path.as_os_str().to_owned();
+        rotated.push(".1");
+        fs::rename(path, rotated)?;
+    }
+
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+
+    writeln!(
+        file,
+        "{} {}",
+        entry.time.format("%Y-%m-%d"),
+        entry.summary()
+    )?;
+
+    for line in &entry.output {
+        writeln!(file, "    {}", line)?;
+    }
+
+    Ok(())
+}
+
+/// Runs the script with `sh -c` in the background, capturing its output so it doesn't end up on
+/// the terminal. It is added to the log once it has finished (or been killed for taking too long).
+///
+/// The script gets a process group of its own so that anything it starts is killed along with it.
+pub fn run(invocation: Invocation, log: &Arc<Mutex<HookLog>>) {
+    let time = Local::now();
+    let mut command = Command::new("sh");
+    command
+        .arg("-c")
+        .arg(&invocation.script)
+        .envs(invocation.env.iter().map(|(key, value)| (key, value)))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    // Safe as `setpgid` is async-signal-safe and nothing else is done between fork and exec
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setpgid(0, 0) == 0 {
+                Ok(())
+            } else {
+                Err(io::Error::last_os_error())
+            }
+        });
+    }
+
+    let spawned = command.spawn();
+
+    let mut child = match spawned {
+        Ok(child) => child,
+        Err(e) => {
+            log.lock().unwrap().add(Entry {
+                time,
+                hook: invocation.hook,
+                status: Status::Failed(e.to_string()),
+                output: Vec::new(),
+            });
+            return;
+        }
+    };
+
+    log.lock().unwrap().running += 1;
+
+    let (tx, rx) = mpsc::channel();
+    let mut streams = 0;
+
+    if let Some(stdout) = child.stdout.take() {
+        read_all(0, stdout, tx.clone());
+        streams += 1;
+    }
+
+    if let Some(stderr) = child.stderr.take() {
+        read_all(1, stderr, tx);
+        streams += 1;
+    }
+
+    let log = Arc::clone(log);
+
+    thread::spawn(move || {
+        let deadline = Instant::now() + invocation.timeout;
+
+        let status = loop {
+            match child.try_wait() {
+                Ok(Some(status)) => match status.code() {
+                    Some(code) => break Status::Exited(code),
+                    None => break Status::Killed,
+                },
+                Ok(None) if Instant::now() >= deadline => {
+                    // The whole group, `sh` on its own would leave the commands it is running
+                    unsafe {
+                        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+                    }
+                    let _ = child.wait();
+                    break Status::TimedOut(invocation.timeout);
+                }
+                Ok(None) => thread::sleep(POLL_INTERVAL),
+                Err(e) => break Status::Failed(e.to_string()),
+            }
+        };
+
+        // Anything the script started in the background can keep the streams open, so only wait
+        // a little while for them
+        let mut outputs = (0..streams)
+            .filter_map(|_| rx.recv_timeout(Duration::from_millis(500)).ok())
+            .collect::<Vec<(usize, String)>>();
+        outputs.sort_by_key(|(stream, _)| *stream);
+
+        let mut log = log.lock().unwrap();
+        log.running -= 1;
+        log.add(Entry {
+            time,
+            hook: invocation.hook,
+            status,
+            output: outputs
+                .iter()
+                .flat_map(|(_, output)| output.lines())
+                .map(str::to_string)
+                .collect(),
+        });
+        log.finished.notify_all();
+    });
+}
+
+/// Reads the stream to the end on another thread and sends it with its index
+fn read_all<R: Read + Send + 'static>(
+    index: usize,
+    mut stream: R,
+    tx: mpsc::Sender<(usize, String)>,
+) {
+    thread::spawn(move || {
+        let mut bytes = Vec::new();
+        let _ = stream.read_to_end(&mut bytes);
+        let _ = tx.send((index, String::from_utf8_lossy(&bytes).into_owned()));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    fn log(dir: &TestDir) -> Arc<Mutex<HookLog>> {
+        let config = Config::new_from_config_string(&format!(
+            "hook_log_file={}",
+            dir.join("hooks.log").display()
+        ))
+        .unwrap();
+
+        Arc::new(Mutex::new(HookLog::from_config(&config)))
+    }
+
+    /// Runs the script for `work_end` and waits for it to finish
+    fn run_script(script: &str, timeout: Duration, log: &Arc<Mutex<HookLog>>) {
+        run(
+            Invocation {
+                hook: Hook::WorkEnd,
+                script: script.to_string(),
+                env: vec![("TOMATO_STATE", "work".to_string())],
+                timeout,
+            },
+            log,
+        );
+        wait_for_hooks(log, None);
+    }
+
+    /// Whether the process has exited (a zombie that hasn't been reaped yet has exited)
+    fn exited(pid: &str) -> bool {
+        match fs::read_to_string(format!("/proc/{}/stat", pid)) {
+            // The state comes after the name, which is in brackets
+            Ok(stat) => stat
+                .rsplit(')')
+                .next()
+                .unwrap()
+                .trim_start()
+                .starts_with('Z'),
+            Err(_) => true,
+        }
+    }
+
+    #[test]
+    fn output_is_captured() {
+        let dir = TestDir::new();
+        let log = log(&dir);
+
+        run_script(
+            "echo \"$TOMATO_STATE\"; echo oops >&2; exit 3",
+            Duration::from_secs(10),
+            &log,
+        );
+
+        let log = log.lock().unwrap();
+        let entry = log.entries().next().unwrap();
+        assert_eq!(log.running(), 0);
+        assert_eq!(entry.status, Status::Exited(3));
+        assert_eq!(entry.output, ["work", "oops"]);
+        assert_eq!(log.unseen_failures(), 1);
+    }
+
+    #[test]
+    fn timed_out_scripts_are_killed_with_everything_they_started() {
+        let dir = TestDir::new();
+        let log = log(&dir);
+        let pid_file = dir.join("pid");
+
+        let started = Instant::now();
+        run_script(
+            &format!("sleep 30 & echo $! > {}; wait", pid_file.display()),
+            Duration::from_millis(300),
+            &log,
+        );
+        assert!(started.elapsed() < Duration::from_secs(10));
+
+        let entry = log.lock().unwrap().entries().next().unwrap().clone();
+        assert_eq!(entry.status, Status::TimedOut(Duration::from_millis(300)));
+
+        // The signal can take a moment to be delivered
+        let pid = fs::read_to_string(&pid_file).unwrap();
+        let pid = pid.trim();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !exited(pid) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(exited(pid), "sleep {} is still running", pid);
+    }
+
+    #[test]
+    fn the_log_file_is_rotated_once_it_is_too_big() {
+        let dir = TestDir::new();
+        let path = dir.join("hooks.log");
+        let mut rotated = path.clone().into_os_string();
+        rotated.push(".1");
+
+        let entry = |status| Entry {
+            time: Local::now(),
+            hook: Hook::Pause,
+            status,
+            output: vec!["out".to_string()],
+        };
+
+        let old = "x".repeat(MAX_LOG_SIZE as usize + 1);
+        fs::write(&path, &old).unwrap();
+        append(&path, &entry(Status::Exited(0))).unwrap();
+        assert_eq!(fs::read_to_string(&rotated).unwrap(), old);
+
+        // Only the new entry is in the log, it isn't rotated again until it is too big
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(
+            contents.ends_with("pause exited with 0\n    out\n"),
+            "{:?}",
+            contents
+        );
+        append(&path, &entry(Status::Killed)).unwrap();
+        assert_eq!(fs::read_to_string(&rotated).unwrap(), old);
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 4);
+    }
+}
@@ -1,110 +1,306 @@
-use crate::{App, AppState, Config};
+use crate::history::PhaseOutcome;
+use crate::hooks::{self, HookLog};
+use crate::keymap::{Action, KeyMap};
+use crate::notify::Notifier;
+use crate::palette::{self, PaletteCommand};
+use crate::sound::Sounds;
+use crate::webhooks::Webhooks;
+use crate::{App, Config};
 
-use crossterm::event::KeyCode as Key;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+
+/// What the keys are currently being used for
+#[derive(Clone, Debug, PartialEq)]
+pub enum Mode {
+    Normal,
+    /// The help is showing, any key closes it
+    Help,
+    /// Typing into the command palette
+    Command(String),
+}
 
 pub struct InputManager {
-    on_work_start: Option<String>,
-    on_break_start: Option<String>,
+    keymap: KeyMap,
+    mode: Mode,
+    /// Result of the last palette command (only set if it went wrong)
+    message: Option<String>,
+    hook_log: Arc<Mutex<HookLog>>,
+    /// Whether the hook log pane is showing
+    show_log: bool,
+    notifier: Option<Notifier>,
+    sounds: Option<Sounds>,
+    webhooks: Option<Webhooks>,
 }
 
 impl InputManager {
     pub fn new(config: Arc<Config>) -> InputManager {
-        let on_work_start = config.get_string("on_work_start").cloned();
-        let on_break_start = config.get_string("on_break_start").cloned();
+        let hook_log = Arc::new(Mutex::new(HookLog::from_config(&config)));
 
-        // TODO: Use config for keys
         InputManager {
-            on_work_start,
-            on_break_start,
+            keymap: KeyMap::from_config(&config),
+            mode: Mode::Normal,
+            message: None,
+            webhooks: Webhooks::from_config(&config, &hook_log),
+            hook_log,
+            show_log: false,
+            notifier: None,
+            sounds: None,
         }
     }
 
+    pub fn set_notifier(&mut self, notifier: Option<Notifier>) {
+        self.notifier = notifier;
+    }
+
+    pub fn set_sounds(&mut self, sounds: Option<Sounds>) {
+        self.sounds = sounds;
+    }
+
+    /// Shows a message on the status line (until the palette is next opened)
+    pub fn set_message(&mut self, message: String) {
+        self.message = Some(message);
+    }
+
+    pub fn keymap(&self) -> &KeyMap {
+        &self.keymap
+    }
+
+    pub fn mode(&self) -> &Mode {
+        &self.mode
+    }
+
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    pub fn hook_log(&self) -> MutexGuard<'_, HookLog> {
+        self.hook_log.lock().unwrap()
+    }
+
+    pub fn show_log(&self) -> bool {
+        self.show_log
+    }
+
     /// Handles the input and alters the app accordingly.
     /// Returns false when the app should stop
-    pub fn handle_input(&self, input: Key, app: &mut App) -> bool {
-        match input {
-            Key::Char('q') | Key::Esc => return false,
-            Key::Char('p') => app.toggle_pause(),
-            Key::Right => {
-                app.transition_to_next_state(std::time::Duration::new(0, 0));
-                handle_next_state(
-                    app.get_state(),
-                    self.on_work_start.as_ref(),
-                    self.on_break_start.as_ref(),
-                );
-            }
-            Key::Left => {
-                let (minutes, seconds) = app.time_elapsed();
-
-                if minutes == 0 && seconds < 2 {
-                    app.transition_to_prev_state(std::time::Duration::new(0, 0));
-                    handle_next_state(
-                        app.get_state(),
-                        self.on_work_start.as_ref(),
-                        self.on_break_start.as_ref(),
-                    );
-                } else {
-                    app.reset_timer(false);
+    pub fn handle_input(&mut self, input: KeyEvent, app: &mut App) -> bool {
+        match &mut self.mode {
+            Mode::Normal => {}
+            Mode::Help => {
+                self.mode = Mode::Normal;
+                return true;
+            }
+            Mode::Command(line) => {
+                match input.code {
+                    KeyCode::Esc => self.mode = Mode::Normal,
+                    KeyCode::Enter => {
+                        let line = line.clone();
+                        self.mode = Mode::Normal;
+                        return self.run_command(&line, app);
+                    }
+                    // Backspace on an empty line closes the palette
+                    KeyCode::Backspace if line.is_empty() => self.mode = Mode::Normal,
+                    KeyCode::Backspace => {
+                        line.pop();
+                    }
+                    KeyCode::Char(c)
+                        if !input
+                            .modifiers
+                            .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) =>
+                    {
+                        line.push(c)
+                    }
+                    _ => {}
+                }
+                return true;
+            }
+        }
+
+        match self.keymap.action(input) {
+            Some(action) => self.perform(action, app),
+            None => true,
+        }
+    }
+
+    /// Returns false when the app should stop
+    pub fn perform(&mut self, action: Action, app: &mut App) -> bool {
+        match action {
+            Action::Quit => return false,
+            Action::TogglePause => app.toggle_pause(),
+            Action::Next => self.skip(app),
+            Action::Prev => self.rewind(app),
+            Action::Elongate => app.elongate_break(),
+            Action::NextProfile => app.next_profile(),
+            Action::Seek(seconds) if seconds < 0 => app.rewind_timer(seconds.unsigned_abs()),
+            Action::Seek(seconds) => app.forward_timer(seconds as u64),
+            Action::Help => self.mode = Mode::Help,
+            Action::Command => {
+                self.mode = Mode::Command(String::new());
+                self.message = None;
+            }
+            // Uses the palette so the current label can be edited
+            Action::Label => {
+                self.mode = Mode::Command(format!("label {}", app.label().unwrap_or("")));
+                self.message = None;
+            }
+            Action::TaskDown => {
+                if let Some(tasks) = app.tasks_mut() {
+                    tasks.select_next();
+                }
+            }
+            Action::TaskUp => {
+                if let Some(tasks) = app.tasks_mut() {
+                    tasks.select_prev();
+                }
+            }
+            Action::TaskStart => {
+                let title = app
+                    .tasks()
+                    .and_then(|tasks| tasks.selected_task())
+                    .map(|task| task.title.clone());
+
+                if title.is_some() {
+                    app.set_label(title);
                 }
             }
-            Key::Char('l') => {
-                if app.get_state() == &AppState::ShortBreak {
-                    app.transition_to_state(
-                        AppState::LongBreak(true),
-                        std::time::Duration::new(0, 0),
-                    )
+            Action::TaskDone => {
+                if let Some(tasks) = app.tasks_mut() {
+                    if let Err(e) = tasks.toggle_done() {
+                        self.message = Some(format!("Couldn't save the tasks: {}", e));
+                    }
+                }
+            }
+            Action::HookLog => {
+                self.show_log = !self.show_log;
+                self.hook_log().mark_seen();
+            }
+            Action::TaskEstimate => {
+                if app.tasks().is_some() {
+                    self.mode = Mode::Command("estimate ".to_string());
+                    self.message = None;
                 }
             }
-            Key::Char('-') => app.rewind_timer(1),
-            Key::Char('=') => app.forward_timer(1),
-            Key::Char('[') => app.rewind_timer(5),
-            Key::Char(']') => app.forward_timer(5),
-            Key::Char(',') => app.rewind_timer(60),
-            Key::Char('.') => app.forward_timer(60),
-            _ => {}
         }
 
         true
     }
-}
 
-pub fn handle_next_state(
-    next_state: &AppState,
-    on_work_start: Option<&String>,
-    on_break_start: Option<&String>,
-) {
-    use std::process::Command;
-    match next_state {
-        AppState::LongBreak(_) => {
-            if let Some(script) = on_break_start {
-                Command::new("sh")
-                    .arg("-c")
-                    .env("BREAK_TYPE", "long")
-                    .arg(script)
-                    .spawn()
-                    .expect("failed to execute break (long) start script");
+    /// Runs a line from the command palette, returns false when the app should stop
+    fn run_command(&mut self, line: &str, app: &mut App) -> bool {
+        let result = palette::parse(line).and_then(|command| match command {
+            PaletteCommand::Action(action) => Ok(self.perform(action, app)),
+            PaletteCommand::SetTime(state, seconds) => {
+                app.set_state_time(state, seconds);
+                Ok(true)
             }
-        }
-        AppState::ShortBreak => {
-            if let Some(script) = on_break_start {
-                Command::new("sh")
-                    .arg("-c")
-                    .arg(script)
-                    .env("BREAK_TYPE", "short")
-                    .spawn()
-                    .expect("failed to execute break (short) start script");
+            PaletteCommand::Profile(name) => app.switch_profile(&name).map(|_| true),
+            PaletteCommand::Label(label) => {
+                app.set_label(label);
+                Ok(true)
+            }
+            PaletteCommand::Estimate(estimate) => app
+                .tasks_mut()
+                .ok_or_else(|| "there is no tasks file (set `tasks_file`)".to_string())
+                .and_then(|tasks| tasks.set_estimate(estimate))
+                .map(|_| true),
+        });
+
+        match result {
+            Ok(keep_running) => keep_running,
+            Err(message) => {
+                self.message = Some(message);
+                true
             }
         }
-        AppState::Work => {
-            if let Some(script) = on_work_start {
-                Command::new("sh")
-                    .arg("-c")
-                    .arg(script)
-                    .spawn()
-                    .expect("failed to execute work start script");
+    }
+
+    /// Updates the app, running the hooks for anything that happened
+    pub fn tick(&mut self, app: &mut App) {
+        app.update();
+        self.run_hooks(app);
+    }
+
+    /// Starts the scripts for the hooks the app has triggered, along with their notifications and
+    /// sounds
+    pub fn run_hooks(&mut self, app: &mut App) {
+        for invocation in app.take_invocations() {
+            hooks::run(invocation, &self.hook_log);
+        }
+
+        for trigger in app.take_triggers() {
+            if let Some(notifier) = &mut self.notifier {
+                notifier.handle(trigger.hook, app);
+            }
+
+            if let Some(sounds) = &mut self.sounds {
+                sounds.handle(trigger.hook);
+            }
+
+            if let Some(webhooks) = &self.webhooks {
+                webhooks.handle(&trigger, app);
             }
         }
+
+        if let Some(sounds) = &mut self.sounds {
+            sounds.update(app);
+        }
+
+        if self.show_log {
+            self.hook_log().mark_seen();
+        }
+    }
+
+    /// Waits for the scripts that are still running (they are killed if they run for longer than
+    /// their timeout) and the webhooks that haven't been sent yet, so that the last ones aren't
+    /// cut off when quitting. Between them the webhooks get one `webhook_timeout`, anything still
+    /// queued after that is dropped.
+    pub fn finish_hooks(&self) {
+        let waiting = {
+            let log = self.hook_log();
+            log.running() + log.sending()
+        };
+
+        if waiting > 0 {
+            eprintln!(
+                "Waiting for {} hook{} to finish (Ctrl-C to stop waiting)...",
+                waiting,
+                if waiting == 1 { "" } else { "s" }
+            );
+        }
+
+        let deadline = self
+            .webhooks
+            .as_ref()
+            .map(|webhooks| Instant::now() + webhooks.timeout());
+
+        hooks::wait_for_hooks(&self.hook_log, deadline);
+    }
+
+    /// Skips straight to the next state, a work session in overtime is finished rather than
+    /// skipped. The next state starts straight away even if it would wait to be started when it
+    /// runs out, skipping (or the notification's button) is asking for it to start.
+    pub fn skip(&self, app: &mut App) {
+        let outcome = if app.is_overtime() {
+            PhaseOutcome::Completed
+        } else {
+            PhaseOutcome::Skipped
+        };
+
+        app.transition_to_next_state(std::time::Duration::new(0, 0), outcome);
+    }
+
+    /// Goes back to the start of the current state, or to the previous state if already (roughly)
+    /// at the start of the current state
+    pub fn rewind(&self, app: &mut App) {
+        let (minutes, seconds) = app.time_elapsed();
+
+        if minutes == 0 && seconds < 2 {
+            app.transition_to_prev_state(std::time::Duration::new(0, 0));
+        } else {
+            app.reset_timer(false);
+        }
     }
 }
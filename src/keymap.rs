@@ -0,0 +1,489 @@
+use crate::config::{Config, Severity};
+use crate::duration;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use std::collections::HashMap;
+
+/// Something that can be bound to a key
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Quit,
+    TogglePause,
+    /// Skip to the next state
+    Next,
+    /// Go back to the start of the state (or the previous state)
+    Prev,
+    Elongate,
+    NextProfile,
+    /// Move the timer forwards (or backwards if negative) by a number of seconds
+    Seek(i64),
+    /// Show the keys and commands
+    Help,
+    /// Open the command palette
+    Command,
+    /// Ask for the label of the current task
+    Label,
+    /// Move the cursor in the task list
+    TaskDown,
+    TaskUp,
+    /// Start working on the task under the cursor (sets the label to it)
+    TaskStart,
+    /// Mark the task under the cursor as done (or not done)
+    TaskDone,
+    /// Ask for the number of pomodoros the task under the cursor should take
+    TaskEstimate,
+    /// Show or hide the output of the hooks
+    HookLog,
+}
+
+/// Every action apart from seeking, which has a binding for each offset
+const ACTIONS: &[Action] = &[
+    Action::Quit,
+    Action::TogglePause,
+    Action::Next,
+    Action::Prev,
+    Action::Elongate,
+    Action::NextProfile,
+    Action::Help,
+    Action::Command,
+    Action::Label,
+    Action::TaskDown,
+    Action::TaskUp,
+    Action::TaskStart,
+    Action::TaskDone,
+    Action::TaskEstimate,
+    Action::HookLog,
+];
+
+const DEFAULT_BINDINGS: &[(&str, Action)] = &[
+    ("q", Action::Quit),
+    ("Esc", Action::Quit),
+    ("p", Action::TogglePause),
+    ("Right", Action::Next),
+    ("Left", Action::Prev),
+    ("l", Action::Elongate),
+    ("Tab", Action::NextProfile),
+    ("-", Action::Seek(-1)),
+    ("=", Action::Seek(1)),
+    ("[", Action::Seek(-5)),
+    ("]", Action::Seek(5)),
+    (",", Action::Seek(-60)),
+    (".", Action::Seek(60)),
+    ("?", Action::Help),
+    (":", Action::Command),
+    ("t", Action::Label),
+    ("j", Action::TaskDown),
+    ("Down", Action::TaskDown),
+    ("k", Action::TaskUp),
+    ("Up", Action::TaskUp),
+    ("Enter", Action::TaskStart),
+    ("x", Action::TaskDone),
+    ("e", Action::TaskEstimate),
+    ("o", Action::HookLog),
+];
+
+impl Action {
+    /// Name of the action in the config (`keybindings.NAME`)
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Quit => "quit",
+            Action::TogglePause => "toggle_pause",
+            Action::Next => "next",
+            Action::Prev => "prev",
+            Action::Elongate => "elongate",
+            Action::NextProfile => "next_profile",
+            Action::Seek(_) => "seek",
+            Action::Help => "help",
+            Action::Command => "command",
+            Action::Label => "label",
+            Action::TaskDown => "task_down",
+            Action::TaskUp => "task_up",
+            Action::TaskStart => "task_start",
+            Action::TaskDone => "task_done",
+            Action::TaskEstimate => "task_estimate",
+            Action::HookLog => "hook_log",
+        }
+    }
+
+    /// What the action does, shown in the help
+    pub fn description(self) -> String {
+        match self {
+            Action::Quit => "Quit".to_string(),
+            Action::TogglePause => "Pause or resume".to_string(),
+            Action::Next => "Skip to the next state".to_string(),
+            Action::Prev => "Back to the start of this state (or the previous state)".to_string(),
+            Action::Elongate => "Turn a short break into a long break".to_string(),
+            Action::NextProfile => "Switch to the next profile".to_string(),
+            Action::Seek(seconds) if seconds < 0 => format!("Rewind by {}s", -seconds),
+            Action::Seek(seconds) => format!("Forward by {}s", seconds),
+            Action::Help => "Show this help".to_string(),
+            Action::Command => "Open the command palette".to_string(),
+            Action::Label => "Set the label of the current task".to_string(),
+            Action::TaskDown => "Select the next task".to_string(),
+            Action::TaskUp => "Select the previous task".to_string(),
+            Action::TaskStart => "Start working on the selected task".to_string(),
+            Action::TaskDone => "Mark the selected task as done (or not done)".to_string(),
+            Action::TaskEstimate => "Estimate the pomodoros for the selected task".to_string(),
+            Action::HookLog => "Show or hide the output of the hooks".to_string(),
+        }
+    }
+
+    /// The name with the offset for seeking (`seek +5s`)
+    pub fn describe(self) -> String {
+        match self {
+            Action::Seek(seconds) => format!(
+                "seek {}{}s",
+                if seconds < 0 { '-' } else { '+' },
+                seconds.abs()
+            ),
+            action => action.name().to_string(),
+        }
+    }
+}
+
+/// Parses a key like `q`, `Esc`, `C-c` (control) or `A-Left` (alt)
+pub fn parse_key(s: &str) -> Result<KeyEvent, String> {
+    let mut modifiers = KeyModifiers::empty();
+    let mut rest = s;
+
+    // The length check means `-` and `C--` still work
+    while rest.len() > 2 {
+        if let Some(after) = rest.strip_prefix("C-") {
+            modifiers |= KeyModifiers::CONTROL;
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("A-") {
+            modifiers |= KeyModifiers::ALT;
+            rest = after;
+        } else {
+            break;
+        }
+    }
+
+    let mut chars = rest.chars();
+    let code = match (chars.next(), chars.next()) {
+        (Some(c), None) => KeyCode::Char(c),
+        _ => match rest.to_lowercase().as_str() {
+            "esc" => KeyCode::Esc,
+            "enter" => KeyCode::Enter,
+            "tab" => KeyCode::Tab,
+            "backtab" => KeyCode::BackTab,
+            "backspace" => KeyCode::Backspace,
+            "delete" => KeyCode::Delete,
+            "insert" => KeyCode::Insert,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "space" => KeyCode::Char(' '),
+            name => match name.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+                Some(n) if (1..=12).contains(&n) => KeyCode::F(n),
+                _ => return Err(format!("unknown key \"{}\"", s)),
+            },
+        },
+    };
+
+    Ok(KeyEvent::new(code, modifiers))
+}
+
+/// The opposite of `parse_key`
+pub fn format_key(key: KeyEvent) -> String {
+    let mut s = String::new();
+
+    if key.modifiers.contains(KeyModifiers::CONTROL) {
+        s.push_str("C-");
+    }
+
+    if key.modifiers.contains(KeyModifiers::ALT) {
+        s.push_str("A-");
+    }
+
+    match key.code {
+        KeyCode::Char(' ') => s.push_str("Space"),
+        KeyCode::Char(c) => s.push(c),
+        KeyCode::F(n) => s.push_str(&format!("F{}", n)),
+        code => s.push_str(&format!("{:?}", code)),
+    }
+
+    s
+}
+
+/// Parses the offset of a `keybindings.seek.OFFSET` key, like `+5s` or `-1m`
+pub fn parse_seek(s: &str) -> Result<i64, String> {
+    let (sign, rest) = match s.strip_prefix('-') {
+        Some(rest) => (-1, rest),
+        None => (1, s.strip_prefix('+').unwrap_or(s)),
+    };
+
+    match duration::parse_seconds(rest)? {
+        0 => Err("the offset must not be 0".to_string()),
+        seconds => Ok(sign * seconds as i64),
+    }
+}
+
+/// Shift is already part of the character for character keys (`P` rather than `S-p`)
+fn normalise(key: KeyEvent) -> KeyEvent {
+    match key.code {
+        KeyCode::Char(_) => KeyEvent::new(key.code, key.modifiers - KeyModifiers::SHIFT),
+        _ => key,
+    }
+}
+
+/// A problem with the bindings, `setting` is the config key it should be reported on
+pub struct Conflict {
+    pub setting: String,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// Which action each key does
+pub struct KeyMap {
+    bindings: Vec<(KeyEvent, Action)>,
+}
+
+impl KeyMap {
+    pub fn from_config(config: &Config) -> KeyMap {
+        KeyMap::build(config).0
+    }
+
+    /// Setting any `keybindings.ACTION` replaces the default keys for that action. A key that is
+    /// bound to two different actions in the config is an error, if it was only bound by default
+    /// then the config wins.
+    pub fn build(config: &Config) -> (KeyMap, Vec<Conflict>) {
+        // The setting, its action and keys for everything bound in the config
+        let mut configured: Vec<(String, Action, Vec<KeyEvent>)> = Vec::new();
+
+        for &action in ACTIONS {
+            let setting = format!("keybindings.{}", action.name());
+
+            if let Some(keys) = keys(config, &setting) {
+                configured.push((setting, action, keys));
+            }
+        }
+
+        for setting in config.keys_with_prefix("keybindings.seek.") {
+            if let (Ok(seconds), Some(keys)) = (
+                parse_seek(&setting["keybindings.seek.".len()..]),
+                keys(config, &setting),
+            ) {
+                configured.push((setting, Action::Seek(seconds), keys));
+            }
+        }
+
+        let mut conflicts = Vec::new();
+        // The action and setting each key is bound to
+        let mut bound: HashMap<KeyEvent, (Action, &str)> = HashMap::new();
+        let mut bindings = Vec::new();
+
+        for (setting, action, keys) in &configured {
+            for &key in keys {
+                match bound.get(&key) {
+                    Some((other, other_setting)) if other != action => conflicts.push(Conflict {
+                        setting: setting.clone(),
+                        severity: Severity::Error,
+                        message: format!(
+                            "`{}` is bound to both `{}` and `{}`",
+                            format_key(key),
+                            other_setting,
+                            setting
+                        ),
+                    }),
+                    Some(_) => {}
+                    None => {
+                        bound.insert(key, (*action, setting));
+                        bindings.push((key, *action));
+                    }
+                }
+            }
+        }
+
+        for (key, action) in DEFAULT_BINDINGS {
+            if configured.iter().any(|(_, other, _)| other == action) {
+                continue;
+            }
+
+            let key = parse_key(key).expect("invalid default key binding");
+
+            match bound.get(&key) {
+                Some((_, setting)) => conflicts.push(Conflict {
+                    setting: setting.to_string(),
+                    severity: Severity::Warning,
+                    message: format!(
+                        "`{}` no longer does `{}` (its default binding)",
+                        format_key(key),
+                        action.describe()
+                    ),
+                }),
+                None => {
+                    bound.insert(key, (*action, ""));
+                    bindings.push((key, *action));
+                }
+            }
+        }
+
+        (KeyMap { bindings }, conflicts)
+    }
+
+    /// Every action with the keys bound to it, with seeking last (shortest offset first)
+    pub fn actions(&self) -> Vec<(Action, Vec<KeyEvent>)> {
+        let mut actions: Vec<(Action, Vec<KeyEvent>)> = Vec::new();
+
+        for &(key, action) in &self.bindings {
+            match actions.iter_mut().find(|(other, _)| *other == action) {
+                Some((_, keys)) => keys.push(key),
+                None => actions.push((action, vec![key])),
+            }
+        }
+
+        actions.sort_by_key(|(action, _)| match action {
+            Action::Seek(seconds) => (ACTIONS.len(), seconds.abs(), *seconds),
+            action => (
+                ACTIONS
+                    .iter()
+                    .position(|other| other == action)
+                    .unwrap_or(0),
+                0,
+                0,
+            ),
+        });
+
+        actions
+    }
+
+    pub fn action(&self, key: KeyEvent) -> Option<Action> {
+        let key = normalise(key);
+
+        self.bindings
+            .iter()
+            .find(|(bound, _)| *bound == key)
+            .map(|(_, action)| *action)
+    }
+}
+
+/// The keys from the config, `None` if the setting isn't set. Invalid keys have already been
+/// reported when the config was loaded so they are skipped.
+fn keys(config: &Config, setting: &str) -> Option<Vec<KeyEvent>> {
+    config
+        .get_list(setting)
+        .map(|keys| keys.iter().filter_map(|key| parse_key(key).ok()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(s: &str) -> KeyEvent {
+        parse_key(s).unwrap()
+    }
+
+    /// The bindings and the conflicts as `(setting, severity, message)`
+    fn build(config: &str) -> (KeyMap, Vec<(String, Severity, String)>) {
+        // The config itself would fail to load with a conflict, so it is built from the raw
+        // bindings without any checks
+        let mut raw = Config::new();
+        for line in config.lines() {
+            let (setting, value) = line.split_once('=').unwrap();
+            raw.set(setting, value).unwrap();
+        }
+
+        let (keymap, conflicts) = KeyMap::build(&raw);
+        let conflicts = conflicts
+            .into_iter()
+            .map(|conflict| (conflict.setting, conflict.severity, conflict.message))
+            .collect();
+
+        (keymap, conflicts)
+    }
+
+    #[test]
+    fn keys_are_formatted_the_way_they_are_read() {
+        for s in [
+            "q", "-", "C--", "C-c", "A-Left", "C-A-x", "Enter", "Space", "F12", "BackTab",
+        ] {
+            assert_eq!(format_key(key(s)), s);
+        }
+
+        // Names aren't case sensitive
+        assert_eq!(key("esc"), key("Esc"));
+        assert_eq!(
+            key("pageup"),
+            KeyEvent::new(KeyCode::PageUp, KeyModifiers::empty())
+        );
+
+        for s in ["", "F13", "F0", "Ctrl-c", "nope"] {
+            assert!(parse_key(s).is_err(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn seek_offsets_have_a_sign() {
+        assert_eq!(parse_seek("+5s"), Ok(5));
+        assert_eq!(parse_seek("-1m"), Ok(-60));
+        assert_eq!(parse_seek("90"), Ok(90));
+        assert!(parse_seek("0s").is_err());
+        assert!(parse_seek("+soon").is_err());
+    }
+
+    #[test]
+    fn configured_keys_replace_the_defaults() {
+        let (keymap, conflicts) = build("keybindings.quit=C-c\nkeybindings.seek.+2m=C-Right");
+        assert!(conflicts.is_empty());
+
+        assert_eq!(keymap.action(key("C-c")), Some(Action::Quit));
+        assert_eq!(keymap.action(key("q")), None);
+        assert_eq!(keymap.action(key("C-Right")), Some(Action::Seek(120)));
+        assert_eq!(keymap.action(key("p")), Some(Action::TogglePause));
+
+        // Shift is part of the character
+        let shifted = KeyEvent::new(KeyCode::Char('p'), KeyModifiers::SHIFT);
+        assert_eq!(keymap.action(shifted), Some(Action::TogglePause));
+    }
+
+    #[test]
+    fn conflicts_are_found() {
+        // Taking a default key away from an action is a warning
+        let (keymap, conflicts) = build("keybindings.next=p");
+        assert_eq!(keymap.action(key("p")), Some(Action::Next));
+        assert_eq!(
+            conflicts,
+            [(
+                "keybindings.next".to_string(),
+                Severity::Warning,
+                "`p` no longer does `toggle_pause` (its default binding)".to_string()
+            )]
+        );
+
+        // Binding a key to two actions in the config is an error, the first one keeps it
+        let (keymap, conflicts) = build("keybindings.next=h\nkeybindings.quit=h");
+        assert_eq!(keymap.action(key("h")), Some(Action::Quit));
+        assert_eq!(
+            conflicts,
+            [(
+                "keybindings.next".to_string(),
+                Severity::Error,
+                "`h` is bound to both `keybindings.quit` and `keybindings.next`".to_string()
+            )]
+        );
+
+        // Which stops the config from loading, pointing at the setting that lost the key
+        let error =
+            Config::new_from_config_string("keybindings.next=h\nkeybindings.quit=h").unwrap_err();
+        assert_eq!(
+            error.diagnostics[0].to_string(),
+            "1:1: error: `h` is bound to both `keybindings.quit` and `keybindings.next`"
+        );
+
+        // Even when it is seeking by different amounts
+        let (_, conflicts) = build("keybindings.seek.+5s=s\nkeybindings.seek.-5s=s");
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].1, Severity::Error);
+
+        // The same key twice for the same action is fine
+        let (_, conflicts) = build("keybindings.quit=q q");
+        assert!(conflicts.is_empty());
+    }
+}
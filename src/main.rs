@@ -2,20 +2,65 @@ mod event;
 use crate::event::{Event, Events};
 
 mod app;
-use crate::app::{App, AppState};
+use crate::app::App;
 
 mod config;
-use crate::config::Config;
+use crate::config::{Config, Diagnostic};
+
+mod control;
+
+mod daemon;
+
+mod duration;
+
+mod goal;
+use crate::goal::Goal;
+
+mod hooks;
+use crate::hooks::Hook;
+
+mod history;
+use crate::history::History;
+
+mod keymap;
+
+mod notify;
+use crate::notify::Notifier;
+
+mod palette;
+
+mod session;
+use crate::session::SessionFile;
+
+mod sound;
+use crate::sound::Sounds;
+
+mod stats;
+
+mod status;
+use crate::status::Status;
+
+mod tasks;
+use crate::tasks::TaskList;
+
+#[cfg(test)]
+mod test_dir;
+
+mod theme;
 
 mod ui;
 use ui::Ui;
 
+mod webhooks;
+
 mod input;
 use input::InputManager;
 
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use clap::{crate_authors, crate_version, load_yaml, App as Arguments};
+use clap::{crate_authors, crate_version, load_yaml, App as Arguments, ArgMatches};
+use failure::format_err;
 
 fn setup_panic_hook() {
     std::panic::set_hook(Box::new(|info| {
@@ -24,6 +69,245 @@ fn setup_panic_hook() {
     }));
 }
 
+/// The config file given with `--config`, otherwise the first default config file that exists
+fn config_path(matches: &ArgMatches) -> Option<PathBuf> {
+    match matches.value_of("config") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => default_config_paths()
+            .into_iter()
+            .find(|path| path.exists()),
+    }
+}
+
+/// `tomato/tomato.toml` in `$XDG_CONFIG_HOME` then each of `$XDG_CONFIG_DIRS`, followed by the
+/// legacy `$HOME/.config/tomato_timer.conf`
+fn default_config_paths() -> Vec<PathBuf> {
+    let system_dirs = std::env::var("XDG_CONFIG_DIRS")
+        .ok()
+        .filter(|dirs| !dirs.is_empty())
+        .unwrap_or_else(|| "/etc/xdg".to_string());
+
+    let mut paths = dirs::config_dir()
+        .into_iter()
+        .chain(std::env::split_paths(&system_dirs))
+        .map(|mut path| {
+            path.push("tomato/tomato.toml");
+            path
+        })
+        .collect::<Vec<_>>();
+
+    if let Some(mut home) = dirs::home_dir() {
+        home.push(".config/tomato_timer.conf");
+        paths.push(home);
+    }
+
+    paths
+}
+
+/// Formats every diagnostic on its own line prefixed with the path, like a compiler would
+fn format_diagnostics(path: &Path, diagnostics: &[Diagnostic]) -> String {
+    diagnostics
+        .iter()
+        .map(|diagnostic| format!("{}:{}", path.display(), diagnostic))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Loads the config, errors are returned and warnings are kept in the config to be shown once it
+/// is known whether the terminal will be taken over
+fn load_config(matches: &ArgMatches) -> Result<Config, failure::Error> {
+    // If default config file couldn't be found then silently just use an empty one
+    let path = match config_path(matches) {
+        Some(path) => path,
+        None => return Ok(Config::new()),
+    };
+
+    let s = std::fs::read_to_string(&path)
+        .map_err(|e| format_err!("Couldn't read config file {}: {}", path.display(), e))?;
+
+    match Config::new_from_path_and_string(&path, &s) {
+        Ok(config) => Ok(config),
+        Err(error) => Err(format_err!(
+            "{}",
+            format_diagnostics(&path, &error.diagnostics)
+        )),
+    }
+}
+
+/// Prints the warnings about the config file to stderr
+fn print_warnings(matches: &ArgMatches, config: &Config) {
+    if let (Some(path), false) = (config_path(matches), config.warnings().is_empty()) {
+        eprintln!("{}", format_diagnostics(&path, config.warnings()));
+    }
+}
+
+/// Command line options that override values from the config file
+const CONFIG_OVERRIDES: &[(&str, &str)] = &[
+    ("work", "work_time"),
+    ("short", "short_break_time"),
+    ("long", "long_break_time"),
+    ("cycle", "pomodoros_before_long_break"),
+    ("tasks", "tasks_file"),
+];
+
+fn apply_overrides(config: &mut Config, matches: &ArgMatches) -> Result<(), failure::Error> {
+    if let Some(profile) = matches.value_of("profile") {
+        config
+            .set_profile(profile)
+            .map_err(|e| format_err!("Invalid value for --profile: {}", e))?;
+    }
+
+    for (arg, key) in CONFIG_OVERRIDES {
+        if let Some(value) = matches.value_of(arg) {
+            config
+                .set(key, value)
+                .map_err(|e| format_err!("Invalid value for --{}: {}", arg, e))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Reports every problem with the config file, returns whether it is valid
+fn check_config(matches: &ArgMatches) -> bool {
+    let path = match config_path(matches) {
+        Some(path) => path,
+        None => {
+            println!("No config file found, the defaults will be used");
+            return true;
+        }
+    };
+
+    let s = match std::fs::read_to_string(&path) {
+        Ok(s) => s,
+        Err(e) => {
+            println!("Couldn't read config file {}: {}", path.display(), e);
+            return false;
+        }
+    };
+
+    let (diagnostics, valid) = match Config::new_from_path_and_string(&path, &s) {
+        Ok(config) => (config.warnings().to_vec(), true),
+        Err(error) => (error.diagnostics, false),
+    };
+
+    if diagnostics.is_empty() {
+        println!("{}: no problems found", path.display());
+    } else {
+        println!("{}", format_diagnostics(&path, &diagnostics));
+    }
+
+    valid
+}
+
+fn parse_date(value: Option<&str>) -> Result<Option<chrono::NaiveDate>, failure::Error> {
+    value
+        .map(|date| {
+            chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .map_err(|_| format_err!("Invalid date \"{}\", expected YYYY-MM-DD", date))
+        })
+        .transpose()
+}
+
+fn run_stats(config: &Config, matches: &ArgMatches) -> Result<(), failure::Error> {
+    let history =
+        History::from_config(config).ok_or_else(|| format_err!("No history file available"))?;
+
+    let grouping = stats::Grouping::from_str(matches.value_of("by").unwrap_or("day"))
+        .ok_or_else(|| format_err!("Unknown grouping"))?;
+    let from = parse_date(matches.value_of("from"))?;
+    let to = parse_date(matches.value_of("to"))?;
+
+    let records = history.read_records()?;
+    let mut summaries = stats::summarise(&records, grouping, from, to);
+
+    // A daily goal doesn't mean anything for a task
+    let goal = Goal::from_config(config).filter(|_| grouping != stats::Grouping::Task);
+
+    if let Some(goal) = goal {
+        stats::count_goal_days(&mut summaries, &records, grouping, from, to, goal);
+    }
+
+    stats::print_summaries(&summaries, grouping, goal);
+
+    Ok(())
+}
+
+/// Sends the command to the daemon and prints the status it replies with
+fn run_command(
+    config: &Config,
+    command: control::Command,
+    matches: &ArgMatches,
+) -> Result<(), failure::Error> {
+    use std::io::Write;
+
+    // Giving a template without a format implies the template should be used
+    let format = if matches.occurrences_of("format") == 0 && matches.is_present("template") {
+        status::Format::Template
+    } else {
+        status::Format::from_str(matches.value_of("format").unwrap_or("text"))
+            .ok_or_else(|| format_err!("Unknown format"))?
+    };
+    let template = matches
+        .value_of("template")
+        .unwrap_or("{state} {time_left}");
+
+    let command = if command == control::Command::Status && matches.is_present("watch") {
+        control::Command::Watch
+    } else {
+        command
+    };
+
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+
+    let argument = matches
+        .values_of("text")
+        .map(|words| words.collect::<Vec<_>>().join(" "));
+
+    for line in control::send(&control::socket_path(config), command, argument.as_deref())? {
+        let line = line?;
+
+        match Status::parse(&line) {
+            Some(status) => writeln!(stdout, "{}", status.format(format, template))?,
+            None => writeln!(stdout, "{}", line)?,
+        }
+
+        // Status bars read line by line so don't wait for the buffer to fill up
+        stdout.flush()?;
+    }
+
+    Ok(())
+}
+
+/// Either resumes the last session or starts a new one
+fn create_app(config: &Arc<Config>, matches: &ArgMatches) -> Result<App, failure::Error> {
+    let snapshot =
+        SessionFile::from_config(config).and_then(|session| session.load().ok().flatten());
+
+    let auto_resume = match (&snapshot, config.get_int("resume_threshold")) {
+        (Some(snapshot), Some(threshold)) => snapshot.age().as_secs() <= threshold,
+        _ => false,
+    };
+
+    let mut app = if matches.is_present("resume") || auto_resume {
+        let snapshot = snapshot.ok_or_else(|| format_err!("There is no session to resume"))?;
+        App::resume(Arc::clone(config), &snapshot)
+    } else {
+        // Create default app state
+        App::new(Arc::clone(config))
+    };
+
+    if let Some(task) = matches.value_of("task") {
+        app.set_label(Some(task.to_string()));
+    }
+
+    app.save_snapshot();
+    app.fire(Hook::Start, Vec::new());
+
+    Ok(app)
+}
+
 fn main() -> Result<(), failure::Error> {
     setup_panic_hook();
 
@@ -33,56 +317,102 @@ fn main() -> Result<(), failure::Error> {
         .author(crate_authors!())
         .get_matches();
 
+    if let ("config", Some(_)) = matches.subcommand() {
+        // The only config subcommand is `check`
+        std::process::exit(if check_config(&matches) { 0 } else { 1 });
+    }
+
+    let config = match load_config(&matches)
+        .and_then(|mut config| apply_overrides(&mut config, &matches).map(|_| config))
+    {
+        Ok(config) => Arc::new(config),
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    };
+
+    // Anything written to stderr would be hidden by the UI, it says where to find them instead
+    if matches.subcommand_name().is_some() {
+        print_warnings(&matches, &config);
+    }
+
+    match matches.subcommand() {
+        ("stats", Some(stats_matches)) => return run_stats(&config, stats_matches),
+        ("daemon", Some(_)) => {
+            let app = create_app(&config, &matches)?;
+            return daemon::run(config, app);
+        }
+        (name, Some(command_matches)) => {
+            if let Some(command) = control::Command::from_str(name) {
+                return run_command(&config, command, command_matches);
+            }
+        }
+        _ => {}
+    }
+
+    let mut app = create_app(&config, &matches)?;
+
     // Setup event handlers
     let events = Events::new(400);
 
-    use std::path::PathBuf;
-
-    let config = Arc::new(
-        matches
-            .value_of("config")
-            .map(PathBuf::from)
-            .map(|path| Config::new_from_config_file(path).expect("Couldn't find your config file"))
-            .or_else(|| {
-                // If there wasn't a config specified try $HOME/.config/tomato_timer.conf
-                dirs::home_dir()
-                    .map(|mut home| {
-                        home.push(".config/tomato_timer.conf");
-                        home
-                    })
-                    // Silently try to get a default config file
-                    .and_then(|path| Config::new_from_config_file(path).ok())
-            })
-            // If default config file couldn't be found then silently just use an empty one
-            .unwrap_or_else(Config::new),
-    );
+    let mut ui = Ui::new_with_termion()?;
+    let mut input_manager = InputManager::new(Arc::clone(&config));
 
-    let on_work_start = config.get_string("on_work_start");
-    let on_break_start = config.get_string("on_break_start");
+    if !config.warnings().is_empty() {
+        input_manager.set_message(format!(
+            "The config file has {} warning(s), run `tomato config check` to see them",
+            config.warnings().len()
+        ));
+    }
 
-    // Create default app state
-    let mut app = App::new(Arc::clone(&config));
-    let mut ui = Ui::new_with_termion()?;
-    let input_manager = InputManager::new(Arc::clone(&config));
+    let tx = events.sender();
+    match Notifier::from_config(&config, move |action| {
+        let _ = tx.send(Event::Action(action));
+    }) {
+        Ok(notifier) => input_manager.set_notifier(notifier),
+        Err(e) => input_manager.set_message(format!("Couldn't show notifications: {}", e)),
+    }
+
+    match Sounds::from_config(&config) {
+        Ok(sounds) => input_manager.set_sounds(sounds),
+        Err(e) => input_manager.set_message(format!("Couldn't play sounds: {}", e)),
+    }
+
+    match TaskList::from_config(&config) {
+        Ok(tasks) => app.set_tasks(tasks),
+        Err(e) => input_manager.set_message(format!("Couldn't load the tasks: {}", e)),
+    }
 
     loop {
-        ui.render(&app)?;
+        ui.render(&app, &input_manager)?;
         match events.next()? {
             Event::Input(input) => {
-                if !input_manager.handle_input(input, &mut app) {
+                let keep_running = input_manager.handle_input(input, &mut app);
+                input_manager.run_hooks(&mut app);
+
+                if !keep_running {
                     // Handle_input has returned false which means that the app should exit
                     break;
                 }
             }
-            Event::Tick => {
-                app.update(&|next_state| {
-                    input::handle_next_state(next_state, on_work_start, on_break_start);
-                });
+            Event::Tick => input_manager.tick(&mut app),
+            Event::Action(action) => {
+                let keep_running = input_manager.perform(action, &mut app);
+                input_manager.tick(&mut app);
+
+                if !keep_running {
+                    break;
+                }
             }
         }
     }
 
     ui::cleanup();
+    app.save_snapshot();
+    app.fire(Hook::Quit, Vec::new());
+    input_manager.run_hooks(&mut app);
+    input_manager.finish_hooks();
 
     let (total_hours, total_mins) = app.total_work_time();
     println!(
@@ -0,0 +1,360 @@
+mod dbus;
+
+use crate::app::App;
+use crate::config::Config;
+use crate::hooks::Hook;
+use crate::keymap::Action;
+use crate::status::Status;
+
+use self::dbus::{Connection, Message, Writer};
+
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+const DEFAULT_SUMMARY: &str = "{state}";
+const DEFAULT_BODY: &str = "{time_left} left - {pomodoros} pomodoros complete";
+
+const DESTINATION: &str = "org.freedesktop.Notifications";
+const PATH: &str = "/org/freedesktop/Notifications";
+const INTERFACE: &str = "org.freedesktop.Notifications";
+
+/// The key of the action on each notification, it skips to the next state
+const NEXT_ACTION: &str = "next";
+
+/// The notification that is currently showing, shared with the thread reading from the bus
+#[derive(Default)]
+struct Shown {
+    /// Serial of the last `Notify` call, its reply has the id of the notification
+    pending: Option<u32>,
+    /// Id of the last notification, 0 if there isn't one
+    id: u32,
+}
+
+/// Shows a desktop notification when a state starts, through the
+/// `org.freedesktop.Notifications` D-Bus interface. Each notification replaces the last one.
+pub struct Notifier {
+    connection: Connection,
+    summary: String,
+    body: String,
+    /// The `urgency` hint, 0 is low, 1 is normal and 2 is critical
+    urgency: u8,
+    actions: bool,
+    shown: Arc<Mutex<Shown>>,
+}
+
+impl Notifier {
+    /// Connects to the session bus unless `notifications.enabled` is false. `on_action` is called
+    /// (on another thread) when one of the buttons on a notification is clicked.
+    ///
+    /// It is only an error if notifications were turned on in the config, otherwise they are
+    /// quietly left off when there is no session bus.
+    pub fn from_config<F>(config: &Config, on_action: F) -> Result<Option<Notifier>, String>
+    where
+        F: Fn(Action) + Send + 'static,
+    {
+        let enabled = config.get_bool("notifications.enabled");
+
+        if enabled == Some(false) {
+            return Ok(None);
+        }
+
+        let connected = dbus::session_bus_address()
+            .ok_or_else(|| "DBUS_SESSION_BUS_ADDRESS isn't set".to_string())
+            .and_then(|address| Notifier::connect(&address, on_action).map_err(|e| e.to_string()));
+
+        let mut notifier = match (connected, enabled) {
+            (Ok(notifier), _) => notifier,
+            (Err(e), Some(true)) => return Err(e),
+            (Err(_), _) => return Ok(None),
+        };
+
+        if let Some(summary) = config.get_string("notifications.summary") {
+            notifier.summary = summary.clone();
+        }
+
+        if let Some(body) = config.get_string("notifications.body") {
+            notifier.body = body.clone();
+        }
+
+        notifier.urgency = match config
+            .get_string("notifications.urgency")
+            .map(String::as_str)
+        {
+            Some("low") => 0,
+            Some("critical") => 2,
+            _ => 1,
+        };
+        notifier.actions = config.get_bool("notifications.actions").unwrap_or(true);
+
+        Ok(Some(notifier))
+    }
+
+    fn connect<F>(address: &str, on_action: F) -> io::Result<Notifier>
+    where
+        F: Fn(Action) + Send + 'static,
+    {
+        let mut connection = Connection::connect(address)?;
+
+        // The signals for clicked and closed notifications
+        let mut rule = Writer::new();
+        rule.string(&format!(
+            "type='signal',interface='{}',path='{}'",
+            INTERFACE, PATH
+        ));
+        connection.call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "AddMatch",
+            "s",
+            rule.into_bytes(),
+        )?;
+
+        let shown = Arc::new(Mutex::new(Shown::default()));
+        let mut reader = connection.reader()?;
+        let reader_shown = Arc::clone(&shown);
+
+        thread::spawn(move || {
+            loop {
+                match dbus::read_message(&mut reader) {
+                    Ok(message) => {
+                        if let Some(action) = handle_message(&message, &reader_shown) {
+                            on_action(action);
+                        }
+                    }
+                    // Something that can't be understood isn't anything to do with notifications
+                    Err(e) if e.kind() == io::ErrorKind::InvalidData => {}
+                    // The connection is closed
+                    Err(_) => break,
+                }
+            }
+        });
+
+        Ok(Notifier {
+            connection,
+            summary: DEFAULT_SUMMARY.to_string(),
+            body: DEFAULT_BODY.to_string(),
+            urgency: 1,
+            actions: true,
+            shown,
+        })
+    }
+
+    /// Shows a notification for the hooks that start a state (or tell you a work session is over),
+    /// everything else is ignored
+    pub fn handle(&mut self, hook: Hook, app: &App) {
+        let action = match hook {
+            Hook::WorkStart | Hook::Overtime => "Start break",
+            Hook::BreakStart => "Skip",
+            _ => return,
+        };
+
+        let status = Status::from_app(app);
+        let summary = status.to_template(&self.summary);
+        let body = status.to_template(&self.body);
+
+        self.show(&summary, &body, action);
+    }
+
+    /// Replaces the last notification, `action` is the label of the button that moves on
+    fn show(&mut self, summary: &str, body: &str, action: &str) {
+        let replaces = {
+            let mut shown = self.shown.lock().unwrap();
+            // Expected before the call is made so the reply can't arrive first. The lock isn't
+            // held during the call so the reader isn't held up if the bus is slow.
+            shown.pending = Some(self.connection.next_serial());
+            shown.id
+        };
+        let actions: &[(&str, &str)] = if self.actions {
+            &[(NEXT_ACTION, action)]
+        } else {
+            &[]
+        };
+
+        // Like the hooks notifications are best-effort, the timer carries on without them
+        let _ = self.connection.call(
+            DESTINATION,
+            PATH,
+            INTERFACE,
+            "Notify",
+            "susssasa{sv}i",
+            notify_body(replaces, summary, body, actions, self.urgency),
+        );
+    }
+}
+
+/// The arguments of `Notify`, `replaces` is the id of the notification to replace (or 0)
+fn notify_body(
+    replaces: u32,
+    summary: &str,
+    body: &str,
+    actions: &[(&str, &str)],
+    urgency: u8,
+) -> Vec<u8> {
+    let mut writer = Writer::new();
+    writer.string("tomato");
+    writer.u32(replaces);
+    // No icon
+    writer.string("");
+    writer.string(summary);
+    writer.string(body);
+    writer.array(4, |writer| {
+        for (key, label) in actions {
+            writer.string(key);
+            writer.string(label);
+        }
+    });
+    writer.array(8, |writer| {
+        writer.start_struct();
+        writer.string("urgency");
+        writer.signature("y");
+        writer.byte(urgency);
+    });
+    // The server decides when it goes away
+    writer.i32(-1);
+
+    writer.into_bytes()
+}
+
+/// Keeps track of the notification that is showing, returning the action to perform if one of
+/// its buttons was clicked
+fn handle_message(message: &Message, shown: &Mutex<Shown>) -> Option<Action> {
+    let mut shown = shown.lock().unwrap();
+
+    if message.kind == dbus::METHOD_RETURN && message.reply_serial == shown.pending {
+        shown.pending = None;
+        shown.id = message.body.first().and_then(|id| id.as_u32()).unwrap_or(0);
+        return None;
+    }
+
+    if message.kind != dbus::SIGNAL || message.interface.as_deref() != Some(INTERFACE) {
+        return None;
+    }
+
+    // Notifications from other programs are ignored
+    let id = message.body.first().and_then(|id| id.as_u32());
+    if shown.id == 0 || id != Some(shown.id) {
+        return None;
+    }
+
+    match message.member.as_deref() {
+        Some("ActionInvoked") => match message.body.get(1).and_then(|key| key.as_str()) {
+            Some(NEXT_ACTION) => Some(Action::Next),
+            _ => None,
+        },
+        Some("NotificationClosed") => {
+            shown.id = 0;
+            None
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::dbus::tests::{garble, reply, signal, Arg, Bus};
+    use super::*;
+
+    use std::sync::mpsc;
+
+    #[test]
+    fn notify_arguments_are_aligned() {
+        let body = notify_body(3, "Work", "25:00 left", &[("next", "Skip")], 2);
+
+        let expected = [
+            // app_name, replaces_id, app_icon
+            &b"\x06\0\0\0tomato\0"[..],
+            b"\0\x03\0\0\0",
+            b"\0\0\0\0\0",
+            // summary, body
+            b"\0\0\0\x04\0\0\0Work\0",
+            b"\0\0\0\x0a\0\0\x0025:00 left\0",
+            // actions, an array of strings 21 bytes long
+            b"\0\x15\0\0\0",
+            b"\x04\0\0\0next\0",
+            b"\0\0\0\x04\0\0\0Skip\0",
+            // hints, the dict entry starts on an 8 byte boundary after the length
+            b"\0\0\0\x10\0\0\0",
+            b"\0\0\0\0",
+            b"\x07\0\0\0urgency\0",
+            b"\x01y\0\x02",
+            // expire_timeout
+            b"\xff\xff\xff\xff",
+        ]
+        .concat();
+        assert_eq!(body, expected);
+    }
+
+    #[test]
+    fn only_our_notifications_act() {
+        let bus = Bus::new();
+        let address = bus.address();
+        let (shown_tx, shown_rx) = mpsc::channel();
+
+        let server = thread::spawn(move || {
+            let mut peer = bus.accept();
+            let (add_match, _) = peer.read();
+            assert_eq!(add_match.member.as_deref(), Some("AddMatch"));
+
+            let (notify, body) = peer.read();
+            assert_eq!(notify.member.as_deref(), Some("Notify"));
+            assert_eq!(
+                body,
+                notify_body(0, "Work", "25:00", &[("next", "Skip")], 1)
+            );
+
+            // The id comes back in the other byte order, it can be either
+            peer.send(&reply(true, 3, &[Arg::U32(42)]));
+
+            let clicked = |big_endian, interface, id, key| {
+                signal(
+                    big_endian,
+                    interface,
+                    "ActionInvoked",
+                    &[Arg::U32(id), Arg::Str(key)],
+                )
+            };
+
+            // Another program's notification, another interface, another button, one that can't
+            // be read and then ours
+            peer.send(&clicked(false, INTERFACE, 7, "next"));
+            peer.send(&clicked(false, "org.example.Other", 42, "next"));
+            peer.send(&clicked(false, INTERFACE, 42, "x"));
+            peer.send(&garble(clicked(false, INTERFACE, 42, "next"), "next"));
+            peer.send(&clicked(true, INTERFACE, 42, "next"));
+
+            // Once it is closed it is no longer ours
+            peer.send(&signal(
+                true,
+                INTERFACE,
+                "NotificationClosed",
+                &[Arg::U32(42), Arg::U32(2)],
+            ));
+            peer.send(&clicked(false, INTERFACE, 42, "next"));
+
+            // Wait for the notifier to catch up before closing the connection
+            shown_rx.recv().unwrap();
+        });
+
+        let (action_tx, action_rx) = mpsc::channel();
+        let mut notifier = Notifier::connect(&address, move |action| {
+            action_tx.send(action).unwrap();
+        })
+        .unwrap();
+        notifier.show("Work", "25:00", "Skip");
+
+        assert_eq!(action_rx.recv().unwrap(), Action::Next);
+
+        // The last signal closed it
+        while notifier.shown.lock().unwrap().id != 0 {
+            thread::yield_now();
+        }
+
+        shown_tx.send(()).unwrap();
+        server.join().unwrap();
+
+        // Nothing else made it through before the connection closed
+        assert_eq!(action_rx.iter().collect::<Vec<_>>(), []);
+    }
+}
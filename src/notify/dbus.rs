@@ -0,0 +1,873 @@
+//! Just enough of the D-Bus wire protocol to send notifications and hear back about them.
+//!
+//! Messages are always written little endian, replies in either byte order can be read. Only the
+//! basic types are understood in message bodies, reading stops at anything more complicated.
+
+use std::convert::{TryFrom, TryInto};
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+pub const METHOD_CALL: u8 = 1;
+pub const METHOD_RETURN: u8 = 2;
+pub const ERROR: u8 = 3;
+pub const SIGNAL: u8 = 4;
+
+// Header fields
+const PATH: u8 = 1;
+const INTERFACE: u8 = 2;
+const MEMBER: u8 = 3;
+const ERROR_NAME: u8 = 4;
+const REPLY_SERIAL: u8 = 5;
+const DESTINATION: u8 = 6;
+const SIGNATURE: u8 = 8;
+
+/// Messages bigger than this are refused rather than allocated (the spec's limit is 128 MiB)
+const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// `$DBUS_SESSION_BUS_ADDRESS`
+pub fn session_bus_address() -> Option<String> {
+    std::env::var("DBUS_SESSION_BUS_ADDRESS").ok()
+}
+
+/// Connects to the first address (they are separated by `;`) that works. Only `unix:path=` and
+/// (on Linux) `unix:abstract=` addresses are supported.
+fn connect_stream(addresses: &str) -> io::Result<UnixStream> {
+    let mut last_error = io::Error::new(io::ErrorKind::InvalidInput, "no bus address");
+
+    for address in addresses.split(';').filter(|address| !address.is_empty()) {
+        match connect_address(address) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = e,
+        }
+    }
+
+    Err(last_error)
+}
+
+fn connect_address(address: &str) -> io::Result<UnixStream> {
+    let unsupported = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unsupported bus address \"{}\"", address),
+        )
+    };
+
+    let options = address.strip_prefix("unix:").ok_or_else(unsupported)?;
+
+    for option in options.split(',') {
+        if let Some(path) = option.strip_prefix("path=") {
+            return UnixStream::connect(unescape(path));
+        }
+
+        #[cfg(target_os = "linux")]
+        {
+            if let Some(name) = option.strip_prefix("abstract=") {
+                use std::os::linux::net::SocketAddrExt;
+                use std::os::unix::net::SocketAddr;
+
+                let address = SocketAddr::from_abstract_name(unescape(name).as_bytes())?;
+                return UnixStream::connect_addr(&address);
+            }
+        }
+    }
+
+    Err(unsupported())
+}
+
+/// Addresses escape bytes as `%xx`
+fn unescape(s: &str) -> String {
+    let mut bytes = Vec::new();
+    let mut rest = s.as_bytes();
+
+    while let Some((&byte, after)) = rest.split_first() {
+        match (byte, after.get(..2)) {
+            (b'%', Some(hex)) => match u8::from_str_radix(&String::from_utf8_lossy(hex), 16) {
+                Ok(value) => {
+                    bytes.push(value);
+                    rest = &after[2..];
+                    continue;
+                }
+                Err(_) => bytes.push(byte),
+            },
+            _ => bytes.push(byte),
+        }
+
+        rest = after;
+    }
+
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// A value in a message body
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Byte(u8),
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Double(f64),
+    /// Strings, object paths and signatures
+    Str(String),
+}
+
+impl Value {
+    pub fn as_u32(&self) -> Option<u32> {
+        match self {
+            Value::UInt(value) => u32::try_from(*value).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Str(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+/// A message that has been read from the bus
+#[derive(Clone, Debug)]
+pub struct Message {
+    pub kind: u8,
+    pub reply_serial: Option<u32>,
+    pub interface: Option<String>,
+    pub member: Option<String>,
+    pub error_name: Option<String>,
+    pub body: Vec<Value>,
+}
+
+/// Builds up the bytes of a message, keeping everything aligned to its size
+#[derive(Default)]
+pub struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Writer {
+        Writer::default()
+    }
+
+    fn align(&mut self, alignment: usize) {
+        while !self.buf.len().is_multiple_of(alignment) {
+            self.buf.push(0);
+        }
+    }
+
+    pub fn byte(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.align(4);
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn i32(&mut self, value: i32) {
+        self.align(4);
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Strings and object paths
+    pub fn string(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.buf.extend_from_slice(value.as_bytes());
+        self.buf.push(0);
+    }
+
+    pub fn signature(&mut self, value: &str) {
+        self.buf.push(value.len() as u8);
+        self.buf.extend_from_slice(value.as_bytes());
+        self.buf.push(0);
+    }
+
+    /// `write` writes the elements, which are aligned to `alignment` (8 for structs and dict
+    /// entries)
+    pub fn array<F: FnOnce(&mut Writer)>(&mut self, alignment: usize, write: F) {
+        self.u32(0);
+        let length_at = self.buf.len() - 4;
+        self.align(alignment);
+
+        let start = self.buf.len();
+        write(self);
+        let length = (self.buf.len() - start) as u32;
+
+        self.buf[length_at..length_at + 4].copy_from_slice(&length.to_le_bytes());
+    }
+
+    /// Structs and dict entries start on an 8 byte boundary
+    pub fn start_struct(&mut self) {
+        self.align(8);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// Reads values out of a message, `big_endian` is from the first byte of the message
+struct Reader<'a> {
+    buf: &'a [u8],
+    position: usize,
+    big_endian: bool,
+}
+
+impl Reader<'_> {
+    fn invalid() -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, "invalid D-Bus message")
+    }
+
+    fn align(&mut self, alignment: usize) {
+        self.position = self.position.next_multiple_of(alignment);
+    }
+
+    fn take(&mut self, length: usize) -> io::Result<&[u8]> {
+        let end = self
+            .position
+            .checked_add(length)
+            .filter(|end| *end <= self.buf.len())
+            .ok_or_else(Reader::invalid)?;
+        let bytes = &self.buf[self.position..end];
+        self.position = end;
+
+        Ok(bytes)
+    }
+
+    fn fixed<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        self.align(N);
+        let mut bytes: [u8; N] = self.take(N)?.try_into().expect("took N bytes");
+
+        if self.big_endian {
+            bytes.reverse();
+        }
+
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.fixed()?))
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let length = self.u32()? as usize;
+        let bytes = self.take(length + 1)?;
+
+        Ok(String::from_utf8_lossy(&bytes[..length]).into_owned())
+    }
+
+    fn signature(&mut self) -> io::Result<String> {
+        let length = self.take(1)?[0] as usize;
+        let bytes = self.take(length + 1)?;
+
+        Ok(String::from_utf8_lossy(&bytes[..length]).into_owned())
+    }
+
+    /// Reads a single basic value, `None` for types that aren't supported
+    fn value(&mut self, code: char) -> io::Result<Option<Value>> {
+        let value = match code {
+            'y' => Value::Byte(self.take(1)?[0]),
+            'b' => Value::Bool(self.u32()? != 0),
+            'n' => Value::Int(i16::from_le_bytes(self.fixed()?) as i64),
+            'q' => Value::UInt(u16::from_le_bytes(self.fixed()?) as u64),
+            'i' => Value::Int(i32::from_le_bytes(self.fixed()?) as i64),
+            'u' => Value::UInt(self.u32()? as u64),
+            'x' => Value::Int(i64::from_le_bytes(self.fixed()?)),
+            't' => Value::UInt(u64::from_le_bytes(self.fixed()?)),
+            'd' => Value::Double(f64::from_le_bytes(self.fixed()?)),
+            's' | 'o' => Value::Str(self.string()?),
+            'g' => Value::Str(self.signature()?),
+            _ => return Ok(None),
+        };
+
+        Ok(Some(value))
+    }
+}
+
+/// Reads the next message from the stream.
+///
+/// An `InvalidData` error is a message that couldn't be understood, all of it has been read so the
+/// next one can be. Any other error means nothing more can be read.
+pub fn read_message<R: Read>(stream: &mut R) -> io::Result<Message> {
+    let mut fixed = [0; 16];
+    stream.read_exact(&mut fixed)?;
+
+    let big_endian = match fixed[0] {
+        b'l' => false,
+        b'B' => true,
+        // Without the byte order there's no knowing where the next message starts
+        _ => return Err(io::Error::other("lost track of the messages from the bus")),
+    };
+
+    let number = |bytes: &[u8]| {
+        let bytes: [u8; 4] = bytes.try_into().expect("4 bytes");
+        if big_endian {
+            u32::from_be_bytes(bytes) as usize
+        } else {
+            u32::from_le_bytes(bytes) as usize
+        }
+    };
+
+    let body_length = number(&fixed[4..8]);
+    let fields_length = number(&fixed[12..16]);
+    let header_length = (16 + fields_length).next_multiple_of(8);
+
+    if header_length + body_length > MAX_MESSAGE_SIZE {
+        let rest = (header_length + body_length - 16) as u64;
+        io::copy(&mut stream.take(rest), &mut io::sink())?;
+        return Err(Reader::invalid());
+    }
+
+    let mut buf = fixed.to_vec();
+    buf.resize(header_length + body_length, 0);
+    stream.read_exact(&mut buf[16..])?;
+
+    let mut message = Message {
+        kind: fixed[1],
+        reply_serial: None,
+        interface: None,
+        member: None,
+        error_name: None,
+        body: Vec::new(),
+    };
+
+    let mut reader = Reader {
+        buf: &buf[..16 + fields_length],
+        position: 16,
+        big_endian,
+    };
+    let mut signature = String::new();
+
+    while reader.position < reader.buf.len() {
+        reader.align(8);
+        let code = reader.take(1)?[0];
+        let field_signature = reader.signature()?;
+        let value = field_signature
+            .chars()
+            .next()
+            .map(|code| reader.value(code))
+            .transpose()?
+            .flatten()
+            .ok_or_else(Reader::invalid)?;
+
+        match (code, value) {
+            (INTERFACE, Value::Str(value)) => message.interface = Some(value),
+            (MEMBER, Value::Str(value)) => message.member = Some(value),
+            (ERROR_NAME, Value::Str(value)) => message.error_name = Some(value),
+            (REPLY_SERIAL, Value::UInt(value)) => message.reply_serial = Some(value as u32),
+            (SIGNATURE, Value::Str(value)) => signature = value,
+            _ => {}
+        }
+    }
+
+    // The body is aligned to the start of the message and starts on an 8 byte boundary, so it
+    // can be read on its own
+    let mut reader = Reader {
+        buf: &buf[header_length..],
+        position: 0,
+        big_endian,
+    };
+
+    for code in signature.chars() {
+        match reader.value(code)? {
+            Some(value) => message.body.push(value),
+            None => break,
+        }
+    }
+
+    Ok(message)
+}
+
+/// A connection to a bus that has been authenticated and said hello
+pub struct Connection {
+    stream: UnixStream,
+    serial: u32,
+}
+
+impl Connection {
+    pub fn connect(address: &str) -> io::Result<Connection> {
+        let mut stream = connect_stream(address)?;
+        // Don't hang if whatever is on the other end never answers
+        stream.set_read_timeout(Some(Duration::from_secs(2)))?;
+        stream.set_write_timeout(Some(Duration::from_secs(2)))?;
+
+        authenticate(&mut stream)?;
+
+        let mut connection = Connection { stream, serial: 0 };
+        let hello = connection.call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "Hello",
+            "",
+            Vec::new(),
+        )?;
+
+        // Anything before the reply (like the NameAcquired signal) doesn't matter
+        loop {
+            let message = read_message(&mut connection.stream)?;
+
+            if message.reply_serial == Some(hello) {
+                if message.kind == ERROR {
+                    return Err(io::Error::other(format!(
+                        "the bus refused the connection: {}",
+                        message.error_name.unwrap_or_default()
+                    )));
+                }
+
+                break;
+            }
+        }
+
+        connection.stream.set_read_timeout(None)?;
+
+        Ok(connection)
+    }
+
+    /// A second handle to the connection for reading messages on another thread
+    pub fn reader(&self) -> io::Result<UnixStream> {
+        self.stream.try_clone()
+    }
+
+    /// The serial the next call will have
+    pub fn next_serial(&self) -> u32 {
+        self.serial + 1
+    }
+
+    /// Sends a method call, returning its serial so the reply can be matched up with it.
+    /// `body` must match `signature`.
+    pub fn call(
+        &mut self,
+        destination: &str,
+        path: &str,
+        interface: &str,
+        member: &str,
+        signature: &str,
+        body: Vec<u8>,
+    ) -> io::Result<u32> {
+        self.serial += 1;
+
+        let mut writer = Writer::new();
+        writer.byte(b'l');
+        writer.byte(METHOD_CALL);
+        // No flags
+        writer.byte(0);
+        // Protocol version
+        writer.byte(1);
+        writer.u32(body.len() as u32);
+        writer.u32(self.serial);
+
+        writer.array(8, |writer| {
+            let mut field = |code: u8, field_signature: &str, value: &str| {
+                writer.start_struct();
+                writer.byte(code);
+                writer.signature(field_signature);
+
+                match field_signature {
+                    "g" => writer.signature(value),
+                    _ => writer.string(value),
+                }
+            };
+
+            field(PATH, "o", path);
+            field(INTERFACE, "s", interface);
+            field(MEMBER, "s", member);
+            field(DESTINATION, "s", destination);
+
+            if !signature.is_empty() {
+                field(SIGNATURE, "g", signature);
+            }
+        });
+
+        writer.start_struct();
+        let mut bytes = writer.into_bytes();
+        bytes.extend_from_slice(&body);
+
+        self.stream.write_all(&bytes)?;
+
+        Ok(self.serial)
+    }
+}
+
+/// The EXTERNAL mechanism, the bus checks the credentials of the socket against the user id
+fn authenticate(stream: &mut UnixStream) -> io::Result<()> {
+    // The user id as hex encoded ASCII digits. Safe as getuid can't fail.
+    let uid = unsafe { libc::getuid() }
+        .to_string()
+        .bytes()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+
+    stream.write_all(format!("\0AUTH EXTERNAL {}\r\n", uid).as_bytes())?;
+
+    loop {
+        let line = read_line(stream)?;
+
+        if line.starts_with("OK") {
+            break;
+        } else if line.starts_with("DATA") {
+            stream.write_all(b"DATA\r\n")?;
+        } else if line.starts_with("REJECTED") {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "the bus rejected the authentication",
+            ));
+        } else {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("unexpected reply from the bus: {}", line),
+            ));
+        }
+    }
+
+    stream.write_all(b"BEGIN\r\n")
+}
+
+/// Reads up to `\r\n` one byte at a time so nothing after it is lost
+fn read_line(stream: &mut UnixStream) -> io::Result<String> {
+    let mut line = Vec::new();
+    let mut byte = [0];
+
+    while !line.ends_with(b"\r\n") {
+        stream.read_exact(&mut byte)?;
+        line.push(byte[0]);
+
+        if line.len() > 4096 {
+            return Err(Reader::invalid());
+        }
+    }
+
+    line.truncate(line.len() - 2);
+    Ok(String::from_utf8_lossy(&line).into_owned())
+}
+
+#[cfg(test)]
+pub mod tests {
+    //! A stand-in for the bus, shared with the notification tests
+
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+
+    /// The values the stand-in bus sends
+    #[derive(Clone, Copy)]
+    pub enum Arg<'a> {
+        U32(u32),
+        Str(&'a str),
+    }
+
+    impl Arg<'_> {
+        fn code(self) -> char {
+            match self {
+                Arg::U32(_) => 'u',
+                Arg::Str(_) => 's',
+            }
+        }
+
+        /// What it is read back as
+        pub fn value(self) -> Value {
+            match self {
+                Arg::U32(value) => Value::UInt(value as u64),
+                Arg::Str(value) => Value::Str(value.to_string()),
+            }
+        }
+    }
+
+    /// Writes messages the way a bus might, in either byte order
+    struct Encoder {
+        buf: Vec<u8>,
+        big_endian: bool,
+    }
+
+    impl Encoder {
+        fn align(&mut self, alignment: usize) {
+            self.buf
+                .resize(self.buf.len().next_multiple_of(alignment), 0);
+        }
+
+        fn u32(&mut self, value: u32) {
+            self.align(4);
+
+            if self.big_endian {
+                self.buf.extend_from_slice(&value.to_be_bytes());
+            } else {
+                self.buf.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+
+        fn string(&mut self, value: &str) {
+            self.u32(value.len() as u32);
+            self.buf.extend_from_slice(value.as_bytes());
+            self.buf.push(0);
+        }
+
+        fn signature(&mut self, value: &str) {
+            self.buf.push(value.len() as u8);
+            self.buf.extend_from_slice(value.as_bytes());
+            self.buf.push(0);
+        }
+
+        fn arg(&mut self, arg: Arg) {
+            match arg {
+                Arg::U32(value) => self.u32(value),
+                Arg::Str(value) => self.string(value),
+            }
+        }
+    }
+
+    /// A whole message, `fields` are header fields (the path is written as an object path)
+    pub fn encode(big_endian: bool, kind: u8, fields: &[(u8, Arg)], body: &[Arg]) -> Vec<u8> {
+        let mut header = Encoder {
+            buf: vec![if big_endian { b'B' } else { b'l' }, kind, 0, 1],
+            big_endian,
+        };
+        let mut content = Encoder {
+            buf: Vec::new(),
+            big_endian,
+        };
+        body.iter().for_each(|arg| content.arg(*arg));
+        let signature = body.iter().map(|arg| arg.code()).collect::<String>();
+
+        header.u32(content.buf.len() as u32);
+        header.u32(1000);
+        header.u32(0);
+        let fields_start = header.buf.len();
+
+        let mut field = |code: u8, field_signature: &str, write: &dyn Fn(&mut Encoder)| {
+            header.align(8);
+            header.buf.push(code);
+            header.signature(field_signature);
+            write(&mut header);
+        };
+
+        for (code, arg) in fields {
+            let field_signature = match (*code, arg.code()) {
+                (PATH, _) => "o",
+                (_, 'u') => "u",
+                _ => "s",
+            };
+            field(*code, field_signature, &|encoder| encoder.arg(*arg));
+        }
+
+        if !signature.is_empty() {
+            field(SIGNATURE, "g", &|encoder| encoder.signature(&signature));
+        }
+
+        let fields_length = (header.buf.len() - fields_start) as u32;
+        let length = if big_endian {
+            fields_length.to_be_bytes()
+        } else {
+            fields_length.to_le_bytes()
+        };
+        header.buf[12..16].copy_from_slice(&length);
+
+        header.align(8);
+        header.buf.extend_from_slice(&content.buf);
+        header.buf
+    }
+
+    /// A signal from `interface`
+    pub fn signal(big_endian: bool, interface: &str, member: &str, body: &[Arg]) -> Vec<u8> {
+        let fields = [
+            (PATH, Arg::Str("/")),
+            (INTERFACE, Arg::Str(interface)),
+            (MEMBER, Arg::Str(member)),
+        ];
+
+        encode(big_endian, SIGNAL, &fields, body)
+    }
+
+    /// The reply to the call with `serial`
+    pub fn reply(big_endian: bool, serial: u32, body: &[Arg]) -> Vec<u8> {
+        encode(
+            big_endian,
+            METHOD_RETURN,
+            &[(REPLY_SERIAL, Arg::U32(serial))],
+            body,
+        )
+    }
+
+    /// A message whose body ends with a string, with that string's length pointing past the end
+    pub fn garble(mut message: Vec<u8>, last_string: &str) -> Vec<u8> {
+        let at = message.len() - last_string.len() - 5;
+        message[at] = 200;
+        message
+    }
+
+    /// The bus end of a connection that has been authenticated and said hello
+    pub struct Peer {
+        stream: UnixStream,
+    }
+
+    impl Peer {
+        /// The next message from the client along with its body as it was sent
+        pub fn read(&mut self) -> (Message, Vec<u8>) {
+            // The client always writes little endian
+            let mut fixed = [0; 16];
+            self.stream.read_exact(&mut fixed).unwrap();
+            let number = |at: usize| u32::from_le_bytes(fixed[at..at + 4].try_into().unwrap());
+            let header_length = (16 + number(12) as usize).next_multiple_of(8);
+
+            let mut bytes = fixed.to_vec();
+            bytes.resize(header_length + number(4) as usize, 0);
+            self.stream.read_exact(&mut bytes[16..]).unwrap();
+
+            let message = read_message(&mut bytes.as_slice()).unwrap();
+            (message, bytes[header_length..].to_vec())
+        }
+
+        pub fn send(&mut self, bytes: &[u8]) {
+            self.stream.write_all(bytes).unwrap();
+        }
+    }
+
+    /// A socket that a client can connect to
+    pub struct Bus {
+        listener: UnixListener,
+        dir: TestDir,
+    }
+
+    impl Bus {
+        pub fn new() -> Bus {
+            let dir = TestDir::new();
+            let listener = UnixListener::bind(dir.join("bus")).unwrap();
+
+            Bus { listener, dir }
+        }
+
+        pub fn address(&self) -> String {
+            // `%2d` is `-`, to check it is unescaped
+            format!(
+                "unix:path={}",
+                self.dir
+                    .join("bus")
+                    .display()
+                    .to_string()
+                    .replace('-', "%2d")
+            )
+        }
+
+        /// Takes the next client through authentication and `Hello`
+        pub fn accept(&self) -> Peer {
+            let (mut stream, _) = self.listener.accept().unwrap();
+
+            // Safe as getuid can't fail
+            let uid = unsafe { libc::getuid() }.to_string();
+            let hex = uid
+                .bytes()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<String>();
+            assert_eq!(
+                read_line(&mut stream).unwrap(),
+                format!("\0AUTH EXTERNAL {}", hex)
+            );
+            stream
+                .write_all(b"OK 0123456789abcdef0123456789abcdef\r\n")
+                .unwrap();
+            assert_eq!(read_line(&mut stream).unwrap(), "BEGIN");
+
+            let mut peer = Peer { stream };
+            let (hello, body) = peer.read();
+            assert_eq!(hello.kind, METHOD_CALL);
+            assert_eq!(hello.interface.as_deref(), Some("org.freedesktop.DBus"));
+            assert_eq!(hello.member.as_deref(), Some("Hello"));
+            assert!(body.is_empty());
+
+            // A signal the client has to get past to find the reply
+            let name = Arg::Str(":1.7");
+            peer.send(&signal(
+                false,
+                "org.freedesktop.DBus",
+                "NameAcquired",
+                &[name],
+            ));
+            peer.send(&reply(false, 1, &[name]));
+
+            peer
+        }
+    }
+
+    #[test]
+    fn calls_go_out_after_authenticating_and_saying_hello() {
+        let bus = Bus::new();
+        let address = bus.address();
+        let server = thread::spawn(move || {
+            let mut peer = bus.accept();
+            let (message, body) = peer.read();
+            assert_eq!(message.kind, METHOD_CALL);
+            assert_eq!(message.interface.as_deref(), Some("org.example.Interface"));
+            assert_eq!(message.member.as_deref(), Some("Method"));
+            assert_eq!(
+                message.body,
+                [Value::UInt(7), Value::Str("seven".to_string())]
+            );
+            body
+        });
+
+        let mut connection = Connection::connect(&address).unwrap();
+        let mut writer = Writer::new();
+        writer.u32(7);
+        writer.string("seven");
+        let serial = connection
+            .call(
+                "org.example",
+                "/org/example",
+                "org.example.Interface",
+                "Method",
+                "us",
+                writer.into_bytes(),
+            )
+            .unwrap();
+
+        // Hello was the first call
+        assert_eq!(serial, 2);
+        assert_eq!(
+            server.join().unwrap(),
+            b"\x07\0\0\0\x05\0\0\0seven\0".to_vec()
+        );
+    }
+
+    #[test]
+    fn messages_are_read_in_either_byte_order() {
+        let body = [Arg::U32(0x0102_0304), Arg::Str("next")];
+        let little = signal(false, "org.example", "Clicked", &body);
+        let big = signal(true, "org.example", "Clicked", &body);
+        assert_ne!(little, big);
+
+        for bytes in &[little, big] {
+            let message = read_message(&mut bytes.as_slice()).unwrap();
+            assert_eq!(message.kind, SIGNAL);
+            assert_eq!(message.interface.as_deref(), Some("org.example"));
+            assert_eq!(message.member.as_deref(), Some("Clicked"));
+            assert_eq!(
+                message.body,
+                body.iter().map(|arg| arg.value()).collect::<Vec<_>>()
+            );
+        }
+
+        let message = read_message(&mut reply(true, 0x0a0b_0c0d, &[]).as_slice()).unwrap();
+        assert_eq!(message.kind, METHOD_RETURN);
+        assert_eq!(message.reply_serial, Some(0x0a0b_0c0d));
+    }
+
+    #[test]
+    fn messages_that_cant_be_read_are_skipped() {
+        let garbled = garble(
+            signal(true, "org.example", "Clicked", &[Arg::Str("next")]),
+            "next",
+        );
+        let mut bytes = [garbled, reply(false, 3, &[])].concat();
+        let mut stream = bytes.as_slice();
+
+        let error = read_message(&mut stream).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(read_message(&mut stream).unwrap().reply_serial, Some(3));
+
+        // Without knowing the byte order there is no telling where the next message starts
+        bytes[0] = b'?';
+        let error = read_message(&mut bytes.as_slice()).unwrap_err();
+        assert_ne!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
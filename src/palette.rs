@@ -0,0 +1,143 @@
+use crate::app::AppState;
+use crate::duration;
+use crate::keymap::{self, Action};
+
+/// Something typed into the command palette (after the `:`)
+#[derive(Clone, Debug, PartialEq)]
+pub enum PaletteCommand {
+    Action(Action),
+    /// Changes the length of a state in seconds
+    SetTime(AppState, u64),
+    Profile(String),
+    /// `None` clears the label
+    Label(Option<String>),
+    /// Sets the estimated number of pomodoros for the selected task
+    Estimate(u64),
+}
+
+/// Usage of each command, shown in the help
+pub const USAGE: &[(&str, &str)] = &[
+    ("work DURATION", "Set the work time"),
+    ("short DURATION", "Set the short break time"),
+    (
+        "long [DURATION]",
+        "Set the long break time (or make this a long break)",
+    ),
+    ("seek OFFSET", "Move the timer by the offset (like -2m)"),
+    ("profile [NAME]", "Switch to the profile (or the next one)"),
+    ("label [TEXT]", "Set the label (or clear it)"),
+    (
+        "estimate COUNT",
+        "Estimate the pomodoros for the selected task",
+    ),
+    ("pause, next, prev, quit, help", "The same as their keys"),
+];
+
+pub fn parse(line: &str) -> Result<PaletteCommand, String> {
+    let line = line.trim();
+    let (name, argument) = match line.find(char::is_whitespace) {
+        Some(i) => (&line[..i], Some(line[i..].trim())),
+        None => (line, None),
+    };
+
+    let time = |state| {
+        let argument =
+            argument.ok_or_else(|| format!("`{}` needs a duration, like `{} 25m`", name, name))?;
+
+        match duration::parse_seconds(argument)? {
+            0 => Err("the duration must be greater than 0".to_string()),
+            seconds => Ok(PaletteCommand::SetTime(state, seconds)),
+        }
+    };
+
+    let action = |action| match argument {
+        Some(_) => Err(format!("`{}` doesn't take an argument", name)),
+        None => Ok(PaletteCommand::Action(action)),
+    };
+
+    match name {
+        "work" => time(AppState::Work),
+        "short" => time(AppState::ShortBreak),
+        "long" if argument.is_some() => time(AppState::LongBreak(false)),
+        "long" => action(Action::Elongate),
+        "seek" => {
+            let offset = argument.ok_or("`seek` needs an offset, like `seek -2m`")?;
+            Ok(PaletteCommand::Action(Action::Seek(keymap::parse_seek(
+                offset,
+            )?)))
+        }
+        "profile" => Ok(match argument {
+            Some(name) => PaletteCommand::Profile(name.to_string()),
+            None => PaletteCommand::Action(Action::NextProfile),
+        }),
+        "label" => Ok(PaletteCommand::Label(argument.map(str::to_string))),
+        "estimate" => argument
+            .and_then(|count| count.parse().ok())
+            .map(PaletteCommand::Estimate)
+            .ok_or_else(|| "`estimate` needs a number of pomodoros, like `estimate 3`".to_string()),
+        "pause" => action(Action::TogglePause),
+        "next" | "skip" => action(Action::Next),
+        "prev" => action(Action::Prev),
+        "quit" => action(Action::Quit),
+        "help" => action(Action::Help),
+        "" => Err("type a command, like `work 40m`".to_string()),
+        _ => Err(format!("unknown command `{}`", name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_are_parsed() {
+        for (line, command) in [
+            ("work 40m", PaletteCommand::SetTime(AppState::Work, 2400)),
+            (
+                "  short   1:30 ",
+                PaletteCommand::SetTime(AppState::ShortBreak, 90),
+            ),
+            (
+                "long 20m",
+                PaletteCommand::SetTime(AppState::LongBreak(false), 1200),
+            ),
+            ("long", PaletteCommand::Action(Action::Elongate)),
+            ("seek -2m", PaletteCommand::Action(Action::Seek(-120))),
+            ("profile deep", PaletteCommand::Profile("deep".to_string())),
+            ("profile", PaletteCommand::Action(Action::NextProfile)),
+            (
+                "label Write  the report",
+                PaletteCommand::Label(Some("Write  the report".to_string())),
+            ),
+            ("label", PaletteCommand::Label(None)),
+            ("skip", PaletteCommand::Action(Action::Next)),
+            ("pause", PaletteCommand::Action(Action::TogglePause)),
+        ] {
+            assert_eq!(parse(line), Ok(command), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn mistakes_say_what_was_expected() {
+        for (line, error) in [
+            ("", "type a command, like `work 40m`"),
+            ("nap 5m", "unknown command `nap`"),
+            ("work", "`work` needs a duration, like `work 25m`"),
+            ("short 0", "the duration must be greater than 0"),
+            ("seek", "`seek` needs an offset, like `seek -2m`"),
+            ("seek 0s", "the offset must not be 0"),
+            ("quit now", "`quit` doesn't take an argument"),
+        ] {
+            assert_eq!(parse(line), Err(error.to_string()), "{:?}", line);
+        }
+
+        assert!(parse("work soon").is_err());
+    }
+
+    #[test]
+    fn estimates_need_a_number() {
+        assert_eq!(parse("estimate 3"), Ok(PaletteCommand::Estimate(3)));
+        assert!(parse("estimate").is_err());
+        assert!(parse("estimate lots").is_err());
+    }
+}
@@ -0,0 +1,243 @@
+use crate::app::AppState;
+use crate::config::{Config, DEFAULT_PROFILE};
+use crate::history::unix_seconds;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Everything needed to rebuild the app exactly as it was
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    pub state: AppState,
+    /// Name of the phase in the cycle
+    pub phase: String,
+    pub pomodoros: u64,
+    /// Length in seconds of each completed work session, `None` for snapshots from before these
+    /// were kept
+    pub completed_work: Option<Vec<u64>>,
+    /// Overtime in seconds of each completed work session (empty for snapshots from before there
+    /// was overtime)
+    pub completed_overtime: Vec<u64>,
+    pub profile: String,
+    /// Time on the timer when the snapshot was taken, including any overtime
+    pub elapsed: Duration,
+    pub paused: bool,
+    /// Waiting to be started rather than paused
+    pub held: bool,
+    pub phase_start: SystemTime,
+    pub label: Option<String>,
+    pub saved_at: SystemTime,
+}
+
+impl Snapshot {
+    /// How long ago the snapshot was taken
+    pub fn age(&self) -> Duration {
+        self.saved_at
+            .elapsed()
+            .unwrap_or_else(|_| Duration::new(0, 0))
+    }
+
+    /// The time that should be on the timer now, if the timer was running when the snapshot was
+    /// taken then it has kept running since
+    pub fn elapsed_now(&self) -> Duration {
+        if self.paused {
+            self.elapsed
+        } else {
+            self.elapsed + self.age()
+        }
+    }
+
+    fn to_file_contents(&self) -> String {
+        format!(
+            "state={}\nphase={}\npomodoros={}\ncompleted_work={}\ncompleted_overtime={}\nprofile={}\nelapsed_millis={}\npaused={}\nheld={}\nphase_start={}\nlabel={}\nsaved_at={}\n",
+            self.state.key(),
+            self.phase,
+            self.pomodoros,
+            join_seconds(self.completed_work.as_deref().unwrap_or(&[])),
+            join_seconds(&self.completed_overtime),
+            self.profile,
+            self.elapsed.as_millis(),
+            self.paused,
+            self.held,
+            unix_seconds(self.phase_start),
+            self.label.as_deref().unwrap_or(""),
+            unix_seconds(self.saved_at)
+        )
+    }
+
+    fn parse(s: &str) -> Option<Snapshot> {
+        let values = s
+            .lines()
+            .filter_map(|line| {
+                let mut parts = line.splitn(2, '=');
+                Some((parts.next()?.trim(), parts.next()?.trim()))
+            })
+            .collect::<HashMap<&str, &str>>();
+
+        let time = |key| -> Option<SystemTime> {
+            Some(UNIX_EPOCH + Duration::from_secs(values.get(key)?.parse().ok()?))
+        };
+
+        // Older snapshots don't have the work sessions, the profile or any overtime
+        let completed_work = match values.get("completed_work") {
+            Some(work) => Some(split_seconds(work)?),
+            None => None,
+        };
+        let completed_overtime = match values.get("completed_overtime") {
+            Some(overtime) => split_seconds(overtime)?,
+            None => Vec::new(),
+        };
+
+        let state = AppState::from_key(values.get("state")?)?;
+        // Older snapshots are from before the cycle could be changed, an elongated break took the
+        // place of a short break
+        let phase = match values.get("phase") {
+            Some(phase) => phase.to_string(),
+            None => match state {
+                AppState::LongBreak(true) => "short_break".to_string(),
+                state => state.key().to_string(),
+            },
+        };
+
+        Some(Snapshot {
+            state,
+            phase,
+            pomodoros: values.get("pomodoros")?.parse().ok()?,
+            completed_work,
+            completed_overtime,
+            profile: values
+                .get("profile")
+                .map_or(DEFAULT_PROFILE, |profile| profile)
+                .to_string(),
+            elapsed: Duration::from_millis(values.get("elapsed_millis")?.parse().ok()?),
+            paused: values.get("paused")?.parse().ok()?,
+            // Older snapshots are from before states could wait to be started
+            held: match values.get("held") {
+                Some(held) => held.parse().ok()?,
+                None => false,
+            },
+            phase_start: time("phase_start")?,
+            // Older snapshots don't have a label
+            label: values
+                .get("label")
+                .filter(|label| !label.is_empty())
+                .map(|label| label.to_string()),
+            saved_at: time("saved_at")?,
+        })
+    }
+}
+
+fn join_seconds(seconds: &[u64]) -> String {
+    seconds
+        .iter()
+        .map(|seconds| seconds.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Parses a comma separated list of seconds
+fn split_seconds(s: &str) -> Option<Vec<u64>> {
+    s.split(',')
+        .filter(|seconds| !seconds.is_empty())
+        .map(|seconds| seconds.parse().ok())
+        .collect()
+}
+
+/// File holding the latest snapshot of the running session
+pub struct SessionFile {
+    path: PathBuf,
+}
+
+impl SessionFile {
+    pub fn new(path: PathBuf) -> SessionFile {
+        SessionFile { path }
+    }
+
+    /// Uses `session_file` from the config if set, otherwise `$XDG_DATA_HOME/tomato/session`
+    pub fn from_config(config: &Config) -> Option<SessionFile> {
+        config
+            .get_string("session_file")
+            .map(PathBuf::from)
+            .or_else(|| {
+                dirs::data_dir().map(|mut path| {
+                    path.push("tomato/session");
+                    path
+                })
+            })
+            .map(SessionFile::new)
+    }
+
+    pub fn save(&self, snapshot: &Snapshot) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Write to a temporary file first so a crash mid-write can't leave a half written snapshot
+        let mut tmp_path = self.path.clone();
+        tmp_path.set_extension("tmp");
+        fs::write(&tmp_path, snapshot.to_file_contents())?;
+        fs::rename(&tmp_path, &self.path)
+    }
+
+    /// Returns `None` if there is no snapshot or it can't be understood
+    pub fn load(&self) -> io::Result<Option<Snapshot>> {
+        match fs::read_to_string(&self.path) {
+            Ok(s) => Ok(Snapshot::parse(&s)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshots_are_written_back_the_way_they_were_read() {
+        let contents = "state=long_break_elongated\n\
+                        phase=long_break\n\
+                        pomodoros=4\n\
+                        completed_work=1500,1500,1620,1500\n\
+                        completed_overtime=0,0,120,0\n\
+                        profile=deep\n\
+                        elapsed_millis=61500\n\
+                        paused=true\n\
+                        held=true\n\
+                        phase_start=1700000000\n\
+                        label=Write = report\n\
+                        saved_at=1700000061\n";
+
+        let snapshot = Snapshot::parse(contents).unwrap();
+        assert_eq!(snapshot.state, AppState::LongBreak(true));
+        assert_eq!(snapshot.elapsed, Duration::from_millis(61500));
+        assert_eq!(snapshot.label.as_deref(), Some("Write = report"));
+
+        assert_eq!(snapshot.to_file_contents(), contents);
+        assert_eq!(
+            Snapshot::parse(&snapshot.to_file_contents()),
+            Some(snapshot)
+        );
+    }
+
+    #[test]
+    fn snapshots_that_cant_be_understood_are_ignored() {
+        let contents =
+            "state=work\npomodoros=1\nelapsed_millis=0\npaused=false\nphase_start=0\nsaved_at=0\n";
+        assert!(Snapshot::parse(contents).is_some());
+
+        assert_eq!(Snapshot::parse(""), None);
+        assert_eq!(Snapshot::parse(&contents.replace("work", "nap")), None);
+        assert_eq!(
+            Snapshot::parse(&contents.replace("pomodoros=1", "pomodoros=-1")),
+            None
+        );
+        assert_eq!(
+            Snapshot::parse(&contents.replace("paused=false", "paused=maybe")),
+            None
+        );
+    }
+}
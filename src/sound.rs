@@ -0,0 +1,117 @@
+#[cfg(feature = "audio")]
+mod audio;
+
+use crate::app::{App, AppState};
+use crate::config::Config;
+use crate::hooks::Hook;
+
+use std::io::{self, Write};
+
+/// Things that have a sound
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cue {
+    WorkStart,
+    BreakStart,
+    /// There is `time_left_warning` left
+    TimeLeft,
+}
+
+enum Player {
+    /// Plays the sounds on another thread
+    #[cfg(feature = "audio")]
+    Audio(audio::Player),
+    /// Rings the terminal bell instead, there is no ticking
+    Bell,
+}
+
+impl Player {
+    #[cfg(feature = "audio")]
+    fn from_config(config: &Config) -> Result<Player, String> {
+        let files = audio::Files::from_config(config)?;
+        let volume = config.get_int("sound.volume").unwrap_or(100) as f32 / 100.0;
+
+        // Without an audio device there is still the bell
+        Ok(audio::Player::start(files, volume)
+            .map(Player::Audio)
+            .unwrap_or(Player::Bell))
+    }
+
+    #[cfg(not(feature = "audio"))]
+    fn from_config(_config: &Config) -> Result<Player, String> {
+        Ok(Player::Bell)
+    }
+
+    /// The bell is the same for every cue
+    #[cfg_attr(not(feature = "audio"), allow(unused_variables))]
+    fn play(&self, cue: Cue) {
+        match self {
+            #[cfg(feature = "audio")]
+            Player::Audio(player) => player.play(cue),
+            Player::Bell => {
+                let mut stdout = io::stdout();
+                let _ = stdout.write_all(b"\x07");
+                let _ = stdout.flush();
+            }
+        }
+    }
+}
+
+/// Plays a sound when a work session or break starts and at the `time_left_warning`, and ticks
+/// during work sessions if `sound.ticking` is on
+pub struct Sounds {
+    player: Player,
+    ticking: bool,
+    /// Whether the player has been told to tick
+    is_ticking: bool,
+}
+
+impl Sounds {
+    /// `sound.enabled` is on by default when built with the `audio` feature. Without the feature
+    /// (or without an audio device) the terminal bell is rung instead.
+    pub fn from_config(config: &Config) -> Result<Option<Sounds>, String> {
+        let enabled = config
+            .get_bool("sound.enabled")
+            .unwrap_or(cfg!(feature = "audio"));
+
+        if !enabled {
+            return Ok(None);
+        }
+
+        Ok(Some(Sounds {
+            player: Player::from_config(config)?,
+            ticking: config.get_bool("sound.ticking").unwrap_or(false),
+            is_ticking: false,
+        }))
+    }
+
+    /// Plays the sound for the hook if it has one
+    pub fn handle(&mut self, hook: Hook) {
+        let cue = match hook {
+            Hook::WorkStart => Cue::WorkStart,
+            // Going into overtime means the work session is up, like the break starting would
+            Hook::BreakStart | Hook::Overtime => Cue::BreakStart,
+            Hook::TimeLeft => Cue::TimeLeft,
+            _ => return,
+        };
+
+        self.player.play(cue);
+    }
+
+    /// Starts or stops the ticking, it only ticks while a work session is running
+    pub fn update(&mut self, app: &App) {
+        let ticking = self.ticking && *app.get_state() == AppState::Work && !app.is_paused();
+
+        if ticking == self.is_ticking {
+            return;
+        }
+
+        self.is_ticking = ticking;
+
+        #[cfg(feature = "audio")]
+        {
+            if let Player::Audio(player) = &self.player {
+                player.set_ticking(ticking);
+            }
+        }
+    }
+}
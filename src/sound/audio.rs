@@ -0,0 +1,292 @@
+//! Plays sounds with rodio, either the files from the config or the chimes built in to the binary
+
+use super::Cue;
+use crate::config::Config;
+
+use rodio::{Decoder, OutputStream, OutputStreamHandle, Source};
+
+use std::f32::consts::PI;
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const SAMPLE_RATE: u32 = 44_100;
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+/// Ticks are quieter than the other sounds
+const TICK_VOLUME: f32 = 0.5;
+
+/// Notes of the chimes, the frequency (in Hz) and when it starts (in seconds)
+const WORK_START: &[(f32, f32)] = &[(523.25, 0.0), (659.25, 0.15), (783.99, 0.3)];
+const BREAK_START: &[(f32, f32)] = &[(783.99, 0.0), (659.25, 0.15), (523.25, 0.3)];
+const TIME_LEFT: &[(f32, f32)] = &[(880.0, 0.0), (880.0, 0.25)];
+const TICK: &[(f32, f32)] = &[(2000.0, 0.0)];
+
+/// A sound file that has been read into memory (and can be decoded)
+#[derive(Clone)]
+struct SoundFile(Arc<[u8]>);
+
+impl SoundFile {
+    fn load(path: &Path) -> Result<SoundFile, String> {
+        let bytes: Arc<[u8]> = fs::read(path).map_err(|e| e.to_string())?.into();
+        Decoder::new(Cursor::new(Arc::clone(&bytes))).map_err(|e| e.to_string())?;
+
+        Ok(SoundFile(bytes))
+    }
+
+    fn decode(&self) -> Option<Decoder<Cursor<Arc<[u8]>>>> {
+        Decoder::new(Cursor::new(Arc::clone(&self.0))).ok()
+    }
+}
+
+/// The WAV or OGG files set in the config, the built in chimes are used for the rest
+pub struct Files {
+    work_start: Option<SoundFile>,
+    break_start: Option<SoundFile>,
+    time_left: Option<SoundFile>,
+    tick: Option<SoundFile>,
+}
+
+impl Files {
+    pub fn from_config(config: &Config) -> Result<Files, String> {
+        let load = |key: &str| {
+            config
+                .get_string(key)
+                .map(|path| {
+                    SoundFile::load(Path::new(path))
+                        .map_err(|e| format!("couldn't load `{}` ({}): {}", key, path, e))
+                })
+                .transpose()
+        };
+
+        Ok(Files {
+            work_start: load("sound.work_start")?,
+            break_start: load("sound.break_start")?,
+            time_left: load("sound.time_left")?,
+            tick: load("sound.tick")?,
+        })
+    }
+
+    fn get(&self, cue: Cue) -> Option<&SoundFile> {
+        match cue {
+            Cue::WorkStart => self.work_start.as_ref(),
+            Cue::BreakStart => self.break_start.as_ref(),
+            Cue::TimeLeft => self.time_left.as_ref(),
+        }
+    }
+}
+
+enum Message {
+    Play(Cue),
+    Ticking(bool),
+}
+
+/// Plays the sounds on its own thread so the timer is never held up by the audio device
+pub struct Player {
+    tx: mpsc::Sender<Message>,
+}
+
+impl Player {
+    /// Returns `None` if there is no audio device.
+    ///
+    /// ALSA writes to stderr when there isn't one, which would end up over the timer. Stderr is
+    /// shared by every thread, so it only goes to `/dev/null` while this waits for the device to be
+    /// opened (this is called once at startup, before any hooks are run).
+    pub fn start(files: Files, volume: f32) -> Option<Player> {
+        let (tx, rx) = mpsc::channel();
+        let (ready_tx, ready_rx) = mpsc::channel();
+        let quiet = QuietStderr::new();
+
+        thread::spawn(move || {
+            // The stream can't be moved between threads, so it is opened on this one
+            let (_stream, handle) = match OutputStream::try_default() {
+                Ok(output) => output,
+                Err(_) => {
+                    let _ = ready_tx.send(false);
+                    return;
+                }
+            };
+
+            let _ = ready_tx.send(true);
+            run(rx, &handle, &files, volume);
+        });
+
+        let ready = ready_rx.recv().unwrap_or(false);
+        drop(quiet);
+
+        if ready {
+            Some(Player { tx })
+        } else {
+            None
+        }
+    }
+
+    pub fn play(&self, cue: Cue) {
+        let _ = self.tx.send(Message::Play(cue));
+    }
+
+    pub fn set_ticking(&self, ticking: bool) {
+        let _ = self.tx.send(Message::Ticking(ticking));
+    }
+}
+
+/// Sends stderr to `/dev/null` until it is dropped
+struct QuietStderr {
+    /// A copy of the real stderr
+    saved: Option<libc::c_int>,
+}
+
+impl QuietStderr {
+    fn new() -> QuietStderr {
+        // Safe as it only swaps file descriptors around, if anything fails stderr is left alone
+        unsafe {
+            let saved = libc::dup(libc::STDERR_FILENO);
+            let null = libc::open(
+                b"/dev/null\0".as_ptr() as *const libc::c_char,
+                libc::O_WRONLY,
+            );
+
+            if saved >= 0 && null >= 0 && libc::dup2(null, libc::STDERR_FILENO) >= 0 {
+                libc::close(null);
+                return QuietStderr { saved: Some(saved) };
+            }
+
+            for fd in &[saved, null] {
+                if *fd >= 0 {
+                    libc::close(*fd);
+                }
+            }
+
+            QuietStderr { saved: None }
+        }
+    }
+}
+
+impl Drop for QuietStderr {
+    fn drop(&mut self) {
+        if let Some(saved) = self.saved {
+            unsafe {
+                libc::dup2(saved, libc::STDERR_FILENO);
+                libc::close(saved);
+            }
+        }
+    }
+}
+
+/// Plays sounds until the player is dropped
+fn run(rx: mpsc::Receiver<Message>, handle: &OutputStreamHandle, files: &Files, volume: f32) {
+    let mut next_tick: Option<Instant> = None;
+
+    loop {
+        let message = match next_tick {
+            Some(at) => rx.recv_timeout(at.saturating_duration_since(Instant::now())),
+            None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+
+        match message {
+            Ok(Message::Play(cue)) => play(handle, files.get(cue), Chime::for_cue(cue), volume),
+            Ok(Message::Ticking(true)) => next_tick = next_tick.or_else(|| Some(Instant::now())),
+            Ok(Message::Ticking(false)) => next_tick = None,
+            Err(RecvTimeoutError::Timeout) => {
+                play(
+                    handle,
+                    files.tick.as_ref(),
+                    Chime::new(TICK, 120.0, 0.05),
+                    volume * TICK_VOLUME,
+                );
+
+                // Don't try to catch up on ticks that were missed
+                next_tick = next_tick.map(|at| (at + TICK_INTERVAL).max(Instant::now()));
+            }
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+/// Plays the file if there is one, otherwise the chime. A sound that can't be played is skipped.
+fn play(handle: &OutputStreamHandle, file: Option<&SoundFile>, chime: Chime, volume: f32) {
+    let _ = match file {
+        Some(file) => match file.decode() {
+            Some(decoder) => handle.play_raw(decoder.convert_samples().amplify(volume)),
+            None => return,
+        },
+        None => handle.play_raw(chime.amplify(volume)),
+    };
+}
+
+/// A few notes that fade out, the built in sounds
+struct Chime {
+    notes: &'static [(f32, f32)],
+    /// How quickly the notes fade out
+    decay: f32,
+    sample: u32,
+    length: u32,
+}
+
+impl Chime {
+    fn new(notes: &'static [(f32, f32)], decay: f32, seconds: f32) -> Chime {
+        Chime {
+            notes,
+            decay,
+            sample: 0,
+            length: (seconds * SAMPLE_RATE as f32) as u32,
+        }
+    }
+
+    fn for_cue(cue: Cue) -> Chime {
+        match cue {
+            Cue::WorkStart => Chime::new(WORK_START, 4.0, 1.5),
+            Cue::BreakStart => Chime::new(BREAK_START, 4.0, 1.5),
+            Cue::TimeLeft => Chime::new(TIME_LEFT, 6.0, 0.8),
+        }
+    }
+}
+
+impl Iterator for Chime {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.sample >= self.length {
+            return None;
+        }
+
+        let time = self.sample as f32 / SAMPLE_RATE as f32;
+        self.sample += 1;
+
+        let value = self
+            .notes
+            .iter()
+            .filter(|(_, start)| time >= *start)
+            .map(|(frequency, start)| {
+                let time = time - start;
+                (2.0 * PI * frequency * time).sin() * (-self.decay * time).exp()
+            })
+            .sum::<f32>();
+
+        // Leaves room for the notes to overlap without clipping
+        Some(value * 0.3)
+    }
+}
+
+impl Source for Chime {
+    fn current_frame_len(&self) -> Option<usize> {
+        Some((self.length - self.sample) as usize)
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        Some(Duration::from_secs_f32(
+            self.length as f32 / SAMPLE_RATE as f32,
+        ))
+    }
+}
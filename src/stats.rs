@@ -0,0 +1,344 @@
+use crate::app::AppState;
+use crate::goal::{Goal, Progress};
+use crate::history::{PhaseOutcome, PhaseRecord};
+
+use chrono::{DateTime, Datelike, Local, NaiveDate};
+
+use std::time::Duration;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Grouping {
+    Day,
+    Week,
+    Month,
+    /// By the label the phases had
+    Task,
+}
+
+impl Grouping {
+    pub fn from_str(s: &str) -> Option<Grouping> {
+        match s {
+            "day" => Some(Grouping::Day),
+            "week" => Some(Grouping::Week),
+            "month" => Some(Grouping::Month),
+            "task" => Some(Grouping::Task),
+            _ => None,
+        }
+    }
+
+    fn heading(self) -> &'static str {
+        match self {
+            Grouping::Task => "Task",
+            _ => "Period",
+        }
+    }
+
+    fn period_name(self, record: &PhaseRecord) -> String {
+        match self {
+            Grouping::Task => record
+                .label
+                .clone()
+                .unwrap_or_else(|| "(no label)".to_string()),
+            _ => self.date_period(record_date(record)),
+        }
+    }
+
+    /// The period a day belongs to (the day itself for task grouping)
+    fn date_period(self, date: NaiveDate) -> String {
+        match self {
+            Grouping::Day | Grouping::Task => date.format("%Y-%m-%d").to_string(),
+            Grouping::Week => {
+                let week = date.iso_week();
+                format!("{}-W{:02}", week.year(), week.week())
+            }
+            Grouping::Month => date.format("%Y-%m").to_string(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Summary {
+    pub period: String,
+    pub pomodoros: u64,
+    pub work_time: Duration,
+    pub break_time: Duration,
+    pub skipped_work: u64,
+    pub skipped_breaks: u64,
+    /// Most work sessions in a row that ran to completion without being skipped
+    pub longest_streak: u64,
+    current_streak: u64,
+    /// Days in the period with any history
+    pub days: u64,
+    /// How many of those days the daily goal was reached on (see `count_goal_days`)
+    pub goal_days: u64,
+}
+
+impl Summary {
+    pub fn progress(&self) -> Progress {
+        Progress {
+            pomodoros: self.pomodoros,
+            work_time: self.work_time,
+        }
+    }
+}
+
+/// Local date that a record belongs to (based on when it started)
+fn record_date(record: &PhaseRecord) -> NaiveDate {
+    DateTime::<Local>::from(record.start).date_naive()
+}
+
+/// What counting a work session did, so that rewinding back over it can undo it
+struct CountedWork {
+    /// Index into the summaries, `None` if the session started outside the range
+    index: Option<usize>,
+    planned: Duration,
+    skipped: bool,
+    /// `current_streak` and `longest_streak` of the summary before the session was counted
+    current_streak: u64,
+    longest_streak: u64,
+}
+
+/// Summarises the records (which must be in the order they were written) that started between
+/// `from` and `to` inclusive.
+///
+/// Pomodoros and work time follow the same rules as the running app: any work session that was
+/// moved on from (either completed or skipped) counts for its full planned time, and rewinding
+/// back into work takes that pomodoro away again when the app did. Records outside the range are
+/// still followed so a rewind is only ever matched with the work session it went back over.
+pub fn summarise(
+    records: &[PhaseRecord],
+    grouping: Grouping,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+) -> Vec<Summary> {
+    let mut summaries: Vec<Summary> = Vec::new();
+    // Every work session that currently counts, the most recent last
+    let mut counted_work: Vec<CountedWork> = Vec::new();
+
+    for record in records {
+        let date = record_date(record);
+        let in_range = from.map(|from| date >= from).unwrap_or(true)
+            && to.map(|to| date <= to).unwrap_or(true);
+
+        let index = if in_range {
+            let period = grouping.period_name(record);
+
+            Some(
+                match summaries
+                    .iter()
+                    .position(|summary| summary.period == period)
+                {
+                    Some(index) => index,
+                    None => {
+                        summaries.push(Summary {
+                            period,
+                            ..Summary::default()
+                        });
+                        summaries.len() - 1
+                    }
+                },
+            )
+        } else {
+            None
+        };
+
+        match (record.state, record.outcome) {
+            (AppState::Work, PhaseOutcome::Completed) | (AppState::Work, PhaseOutcome::Skipped) => {
+                let skipped = record.outcome == PhaseOutcome::Skipped;
+                let mut streaks = (0, 0);
+
+                if let Some(index) = index {
+                    let summary = &mut summaries[index];
+                    streaks = (summary.current_streak, summary.longest_streak);
+                    summary.pomodoros += 1;
+                    summary.work_time += record.planned;
+
+                    if skipped {
+                        summary.skipped_work += 1;
+                        summary.current_streak = 0;
+                    } else {
+                        summary.current_streak += 1;
+                        summary.longest_streak =
+                            std::cmp::max(summary.longest_streak, summary.current_streak);
+                    }
+                }
+
+                counted_work.push(CountedWork {
+                    index,
+                    planned: record.planned,
+                    skipped,
+                    current_streak: streaks.0,
+                    longest_streak: streaks.1,
+                });
+            }
+            (AppState::Work, _) => {}
+            (_, outcome) => {
+                if let Some(index) = index {
+                    let summary = &mut summaries[index];
+                    summary.break_time += record.actual;
+
+                    if outcome == PhaseOutcome::Skipped {
+                        summary.skipped_breaks += 1;
+                    }
+                }
+            }
+        }
+
+        // Older records don't say, but back then only going back from a break into work did
+        let took_back = record.took_back.unwrap_or(record.state != AppState::Work);
+
+        if record.outcome == PhaseOutcome::Rewound && took_back {
+            if let Some(CountedWork {
+                index: Some(index),
+                planned,
+                skipped,
+                current_streak,
+                longest_streak,
+            }) = counted_work.pop()
+            {
+                let summary = &mut summaries[index];
+                summary.pomodoros -= 1;
+                summary.work_time -= planned;
+                summary.current_streak = current_streak;
+                summary.longest_streak = longest_streak;
+
+                if skipped {
+                    summary.skipped_work -= 1;
+                }
+            }
+        }
+    }
+
+    summaries
+}
+
+/// The work done on a single day
+pub fn progress_on(records: &[PhaseRecord], date: NaiveDate) -> Progress {
+    summarise(records, Grouping::Day, Some(date), Some(date))
+        .first()
+        .map(Summary::progress)
+        .unwrap_or_default()
+}
+
+/// Fills in `days` and `goal_days` of summaries grouped by day, week or month
+pub fn count_goal_days(
+    summaries: &mut [Summary],
+    records: &[PhaseRecord],
+    grouping: Grouping,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    goal: Goal,
+) {
+    for day in summarise(records, Grouping::Day, from, to) {
+        let date = NaiveDate::parse_from_str(&day.period, "%Y-%m-%d").expect("days are dates");
+        let period = grouping.date_period(date);
+
+        if let Some(summary) = summaries
+            .iter_mut()
+            .find(|summary| summary.period == period)
+        {
+            summary.days += 1;
+
+            if goal.is_reached(&day.progress()) {
+                summary.goal_days += 1;
+            }
+        }
+    }
+}
+
+/// `hit` or `miss` for a single day, otherwise the number of days the goal was hit on
+fn format_goal_days(summary: &Summary, grouping: Grouping) -> String {
+    match grouping {
+        Grouping::Day if summary.goal_days > 0 => "hit".to_string(),
+        Grouping::Day => "miss".to_string(),
+        _ => format!("{}/{}", summary.goal_days, summary.days),
+    }
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let minutes = duration.as_secs() / 60;
+    format!("{}h{:02}m", minutes / 60, minutes % 60)
+}
+
+/// `goal` adds a column with whether the daily goal was hit (the days must have been counted)
+pub fn print_summaries(summaries: &[Summary], grouping: Grouping, goal: Option<Goal>) {
+    if summaries.is_empty() {
+        println!("No history in this range");
+        return;
+    }
+
+    // Labels can be longer than the dates
+    let width = summaries
+        .iter()
+        .map(|summary| summary.period.chars().count() + 2)
+        .max()
+        .unwrap_or(0)
+        .max(12);
+
+    print!(
+        "{:<width$}{:>10}{:>10}{:>10}{:>15}{:>16}{:>10}",
+        grouping.heading(),
+        "Pomodoros",
+        "Work",
+        "Breaks",
+        "Skipped work",
+        "Skipped breaks",
+        "Streak",
+        width = width
+    );
+
+    if goal.is_some() {
+        print!("{:>8}", "Goal");
+    }
+
+    println!();
+
+    let mut total = Summary::default();
+
+    for summary in summaries {
+        print!(
+            "{:<width$}{:>10}{:>10}{:>10}{:>15}{:>16}{:>10}",
+            summary.period,
+            summary.pomodoros,
+            format_duration(summary.work_time),
+            format_duration(summary.break_time),
+            summary.skipped_work,
+            summary.skipped_breaks,
+            summary.longest_streak,
+            width = width
+        );
+
+        if goal.is_some() {
+            print!("{:>8}", format_goal_days(summary, grouping));
+        }
+
+        println!();
+
+        total.pomodoros += summary.pomodoros;
+        total.work_time += summary.work_time;
+        total.break_time += summary.break_time;
+        total.skipped_work += summary.skipped_work;
+        total.skipped_breaks += summary.skipped_breaks;
+        total.longest_streak = std::cmp::max(total.longest_streak, summary.longest_streak);
+        total.days += summary.days;
+        total.goal_days += summary.goal_days;
+    }
+
+    print!(
+        "{:<width$}{:>10}{:>10}{:>10}{:>15}{:>16}{:>10}",
+        "Total",
+        total.pomodoros,
+        format_duration(total.work_time),
+        format_duration(total.break_time),
+        total.skipped_work,
+        total.skipped_breaks,
+        total.longest_streak,
+        width = width
+    );
+
+    if goal.is_some() {
+        print!("{:>8}", format!("{}/{}", total.goal_days, total.days));
+    }
+
+    println!();
+}
@@ -0,0 +1,284 @@
+use crate::app::App;
+
+use std::collections::HashMap;
+
+/// Snapshot of what the timer is currently showing, sent from the daemon to clients
+#[derive(Clone, Debug, PartialEq)]
+pub struct Status {
+    /// Display name of the state (same as `App::get_state_name`)
+    pub state: String,
+    /// Seconds left in the current state
+    pub time_left: u64,
+    /// Seconds elapsed in the current state
+    pub time_elapsed: u64,
+    /// Seconds past the end of the work session, 0 unless it has gone into overtime
+    pub overtime: u64,
+    /// Fraction of the way through the current state (between 0.0 and 1.0)
+    pub progress: f64,
+    pub pomodoros: u64,
+    pub paused: bool,
+    /// Total work time in seconds (rounded down to the minute like `App::total_work_time`)
+    pub total_work_time: u64,
+    pub label: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+    Template,
+}
+
+impl Format {
+    pub fn from_str(s: &str) -> Option<Format> {
+        match s {
+            "text" => Some(Format::Text),
+            "json" => Some(Format::Json),
+            "template" => Some(Format::Template),
+            _ => None,
+        }
+    }
+}
+
+fn minutes_seconds(seconds: u64) -> String {
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+/// Escapes a string so that it can be put inside quotes in JSON
+pub fn json_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped.push('"');
+    escaped
+}
+
+impl Status {
+    pub fn from_app(app: &App) -> Status {
+        let (left_minutes, left_seconds) = app.time_left();
+        let (elapsed_minutes, elapsed_seconds) = app.time_elapsed();
+        let (overtime_minutes, overtime_seconds) = app.overtime().unwrap_or((0, 0));
+        let (work_hours, work_minutes) = app.total_work_time();
+
+        Status {
+            state: app.get_state_name(),
+            time_left: left_minutes * 60 + left_seconds,
+            time_elapsed: elapsed_minutes * 60 + elapsed_seconds,
+            overtime: overtime_minutes * 60 + overtime_seconds,
+            progress: app.progress(),
+            pomodoros: app.pomodoros(),
+            paused: app.is_paused(),
+            total_work_time: (work_hours * 60 + work_minutes) * 60,
+            label: app.label().map(str::to_string),
+        }
+    }
+
+    /// Single line of tab separated `key=value` pairs used by the socket (`label` is empty if
+    /// there isn't one). Tabs and line breaks in the label would split it up, they are sent as
+    /// spaces.
+    pub fn to_line(&self) -> String {
+        format!(
+            "state={}\ttime_left={}\ttime_elapsed={}\tovertime={}\tprogress={}\tpomodoros={}\tpaused={}\ttotal_work_time={}\tlabel={}",
+            self.state,
+            self.time_left,
+            self.time_elapsed,
+            self.overtime,
+            self.progress,
+            self.pomodoros,
+            self.paused,
+            self.total_work_time,
+            self.label
+                .as_deref()
+                .unwrap_or("")
+                .replace(['\t', '\r', '\n'], " ")
+        )
+    }
+
+    pub fn parse(line: &str) -> Option<Status> {
+        let values = line
+            .trim_end_matches('\n')
+            .split('\t')
+            .filter_map(|part| {
+                let mut parts = part.splitn(2, '=');
+                Some((parts.next()?, parts.next()?))
+            })
+            .collect::<HashMap<&str, &str>>();
+
+        Some(Status {
+            state: values.get("state")?.to_string(),
+            time_left: values.get("time_left")?.parse().ok()?,
+            time_elapsed: values.get("time_elapsed")?.parse().ok()?,
+            // Not sent by older daemons
+            overtime: values
+                .get("overtime")
+                .and_then(|overtime| overtime.parse().ok())
+                .unwrap_or(0),
+            progress: values.get("progress")?.parse().ok()?,
+            pomodoros: values.get("pomodoros")?.parse().ok()?,
+            paused: values.get("paused")?.parse().ok()?,
+            total_work_time: values.get("total_work_time")?.parse().ok()?,
+            label: values
+                .get("label")
+                .filter(|label| !label.is_empty())
+                .map(|label| label.to_string()),
+        })
+    }
+
+    /// `-m:ss` left, or `+m:ss` in overtime
+    fn countdown(&self) -> String {
+        if self.overtime > 0 {
+            format!("+{}", minutes_seconds(self.overtime))
+        } else {
+            format!("-{}", minutes_seconds(self.time_left))
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        format!(
+            "{}{} - {} - {} pomodoros complete{}",
+            self.state,
+            match &self.label {
+                Some(label) => format!(" ({})", label),
+                None => String::new(),
+            },
+            if self.overtime > 0 {
+                format!("{} overtime", minutes_seconds(self.overtime))
+            } else {
+                format!("{} left", minutes_seconds(self.time_left))
+            },
+            self.pomodoros,
+            if self.paused { " (Paused)" } else { "" }
+        )
+    }
+
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"state\":{},\"time_left\":{},\"time_elapsed\":{},\"overtime\":{},\"progress\":{},\"pomodoros\":{},\"paused\":{},\"total_work_time\":{},\"label\":{}}}",
+            json_string(&self.state),
+            self.time_left,
+            self.time_elapsed,
+            self.overtime,
+            self.progress,
+            self.pomodoros,
+            self.paused,
+            self.total_work_time,
+            match &self.label {
+                Some(label) => json_string(label),
+                None => "null".to_string(),
+            }
+        )
+    }
+
+    /// Replaces `{state}`, `{time_left}`, `{time_elapsed}`, `{countdown}`, `{progress}`,
+    /// `{pomodoros}`, `{paused}`, `{work_time}` and `{label}` in the template
+    pub fn to_template(&self, template: &str) -> String {
+        let work_minutes = self.total_work_time / 60;
+
+        template
+            .replace("{state}", &self.state)
+            .replace("{time_left}", &minutes_seconds(self.time_left))
+            .replace("{time_elapsed}", &minutes_seconds(self.time_elapsed))
+            .replace("{countdown}", &self.countdown())
+            .replace(
+                "{progress}",
+                &format!("{}", (self.progress * 100.0).round() as u64),
+            )
+            .replace("{pomodoros}", &self.pomodoros.to_string())
+            .replace("{paused}", if self.paused { "paused" } else { "" })
+            .replace(
+                "{work_time}",
+                &format!("{}h{}m", work_minutes / 60, work_minutes % 60),
+            )
+            .replace("{label}", self.label.as_deref().unwrap_or(""))
+    }
+
+    pub fn format(&self, format: Format, template: &str) -> String {
+        match format {
+            Format::Text => self.to_text(),
+            Format::Json => self.to_json(),
+            Format::Template => self.to_template(template),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status() -> Status {
+        Status {
+            state: "Work".to_string(),
+            time_left: 90,
+            time_elapsed: 1410,
+            overtime: 0,
+            progress: 0.94,
+            pomodoros: 3,
+            paused: true,
+            total_work_time: 4500,
+            label: Some("Write \"the\" report = done\n".to_string()),
+        }
+    }
+
+    #[test]
+    fn lines_are_read_back_as_they_were_sent() {
+        let status = status();
+        let parsed = Status::parse(&status.to_line()).unwrap();
+        assert_eq!(
+            parsed.label.as_deref(),
+            Some("Write \"the\" report = done ")
+        );
+        assert_eq!(
+            parsed,
+            Status {
+                label: parsed.label.clone(),
+                ..status.clone()
+            }
+        );
+
+        let unlabelled = Status {
+            label: None,
+            ..status
+        };
+        assert_eq!(Status::parse(&unlabelled.to_line()), Some(unlabelled));
+
+        // Older daemons don't send the overtime, anything missing otherwise isn't a status
+        let line = "state=Work\ttime_left=90\ttime_elapsed=1410\tprogress=0.94\tpomodoros=3\tpaused=true\ttotal_work_time=4500";
+        assert_eq!(Status::parse(line).map(|status| status.overtime), Some(0));
+        assert_eq!(Status::parse(&line.replace("pomodoros=3\t", "")), None);
+        assert_eq!(Status::parse("error: unknown command \"nap\""), None);
+    }
+
+    #[test]
+    fn json_is_escaped() {
+        assert_eq!(
+            status().to_json(),
+            "{\"state\":\"Work\",\"time_left\":90,\"time_elapsed\":1410,\"overtime\":0,\"progress\":0.94,\"pomodoros\":3,\"paused\":true,\"total_work_time\":4500,\"label\":\"Write \\\"the\\\" report = done\\n\"}"
+        );
+        assert_eq!(json_string("tab\there\u{1}"), "\"tab\\there\\u0001\"");
+    }
+
+    #[test]
+    fn templates_fill_in_every_placeholder() {
+        let status = Status {
+            overtime: 75,
+            ..status()
+        };
+
+        assert_eq!(
+            status.to_template("{state} {countdown} {time_left} {time_elapsed} {progress}% {pomodoros} {paused} {work_time}"),
+            "Work +1:15 1:30 23:30 94% 3 paused 1h15m"
+        );
+    }
+}
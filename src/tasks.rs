@@ -0,0 +1,582 @@
+use crate::config::Config;
+
+use chrono::{Local, NaiveDate};
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The kinds of file tasks can be read from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// One task per line, done tasks start with `x ` (plain text files are read the same way)
+    TodoTxt,
+    /// `- [ ] task` checklist items, anything else in the file is ignored
+    Markdown,
+}
+
+impl Format {
+    /// `.md` and `.markdown` files are Markdown, everything else is todo.txt
+    pub fn from_path(path: &Path) -> Format {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("md") | Some("markdown") => Format::Markdown,
+            _ => Format::TodoTxt,
+        }
+    }
+}
+
+/// A task from one line of the file
+#[derive(Clone, Debug, PartialEq)]
+pub struct Task {
+    /// The text of the task without the priority, dates, projects, contexts or tags, this is what
+    /// the label is set to when it is started
+    pub title: String,
+    pub done: bool,
+    /// todo.txt priority (`(A)`, or `pri:A` once it is done)
+    pub priority: Option<char>,
+    /// todo.txt projects (`+project`)
+    pub projects: Vec<String>,
+    /// todo.txt contexts (`@context`)
+    pub contexts: Vec<String>,
+    /// How many pomodoros the task is expected to take (`est:N`)
+    pub estimate: Option<u64>,
+    /// How many pomodoros have been spent on it (`pomos:N`)
+    pub pomodoros: u64,
+    /// Index into the lines of the file
+    line: usize,
+}
+
+impl Task {
+    fn new(line: usize) -> Task {
+        Task {
+            title: String::new(),
+            done: false,
+            priority: None,
+            projects: Vec::new(),
+            contexts: Vec::new(),
+            estimate: None,
+            pomodoros: 0,
+            line,
+        }
+    }
+
+    /// Reads the words that make up the task, pulling out the projects, contexts and tags
+    fn read_words(&mut self, text: &str) {
+        let mut title = Vec::new();
+
+        for word in text.split_whitespace() {
+            if let Some(value) = word.strip_prefix("est:").and_then(|n| n.parse().ok()) {
+                self.estimate = Some(value);
+            } else if let Some(value) = word.strip_prefix("pomos:").and_then(|n| n.parse().ok()) {
+                self.pomodoros = value;
+            } else if let Some(priority) = priority_tag(word) {
+                self.priority = Some(priority);
+            } else if word.len() > 1 && word.starts_with('+') {
+                self.projects.push(word[1..].to_string());
+            } else if word.len() > 1 && word.starts_with('@') {
+                self.contexts.push(word[1..].to_string());
+            } else {
+                title.push(word);
+            }
+        }
+
+        self.title = title.join(" ");
+    }
+}
+
+/// Returns `None` for lines that aren't tasks (empty lines and `#` comments).
+///
+/// A line is `[x ][(A) ][completion date ][creation date ]text` where the text can contain
+/// `+project`, `@context` and `key:value` tags.
+fn parse_todo_txt(line: &str, index: usize) -> Option<Task> {
+    let line = line.trim();
+
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    let mut task = Task::new(index);
+    let mut rest = line;
+
+    if let Some(after) = rest.strip_prefix("x ") {
+        task.done = true;
+        rest = after.trim_start();
+    }
+
+    if let [b'(', priority, b')', b' ', ..] = rest.as_bytes() {
+        if priority.is_ascii_uppercase() {
+            task.priority = Some(*priority as char);
+            rest = rest[4..].trim_start();
+        }
+    }
+
+    // Completion and creation dates
+    while let Some(date) = rest.split_whitespace().next().filter(|word| is_date(word)) {
+        rest = rest[date.len()..].trim_start();
+    }
+
+    task.read_words(rest);
+
+    Some(task)
+}
+
+fn is_date(word: &str) -> bool {
+    NaiveDate::parse_from_str(word, "%Y-%m-%d").is_ok()
+}
+
+/// The priority in a `pri:A` tag, where the priority of a done task is kept
+fn priority_tag(word: &str) -> Option<char> {
+    match word.strip_prefix("pri:")?.as_bytes() {
+        [priority] if priority.is_ascii_uppercase() => Some(*priority as char),
+        _ => None,
+    }
+}
+
+/// Marks a todo.txt task as done the way the format says to, with `x ` and the date it was
+/// completed in front. Done tasks don't have a priority so it is moved into a `pri:` tag.
+fn mark_done(line: &str, today: NaiveDate) -> String {
+    let (priority, rest) = match line.as_bytes() {
+        [b'(', priority, b')', b' ', ..] if priority.is_ascii_uppercase() => {
+            (Some(*priority as char), line[4..].trim_start())
+        }
+        _ => (None, line),
+    };
+    let done = format!("x {} {}", today.format("%Y-%m-%d"), rest);
+
+    match priority {
+        Some(priority) => format!("{} pri:{}", done.trim_end(), priority),
+        None => done,
+    }
+}
+
+/// Undoes `mark_done`, dropping the completion date and putting the priority back in front
+fn mark_undone(line: &str) -> String {
+    let mut rest = line.strip_prefix("x ").unwrap_or(line).trim_start();
+
+    if let Some(completed) = rest.split(' ').next().filter(|word| is_date(word)) {
+        rest = rest[completed.len()..].trim_start();
+    }
+
+    match rest.split(' ').find_map(priority_tag) {
+        Some(priority) => {
+            let words = rest
+                .split(' ')
+                .filter(|word| priority_tag(word).is_none())
+                .collect::<Vec<_>>();
+            format!("({}) {}", priority, words.join(" "))
+        }
+        None => rest.to_string(),
+    }
+}
+
+/// Byte offset of the character inside the `[ ]` of a checklist item
+fn checkbox_offset(line: &str) -> Option<usize> {
+    let trimmed = line.trim_start();
+    let indent = line.len() - trimmed.len();
+
+    let after_bullet = trimmed
+        .strip_prefix("- ")
+        .or_else(|| trimmed.strip_prefix("* "))
+        .or_else(|| trimmed.strip_prefix("+ "))?;
+
+    match after_bullet.as_bytes() {
+        [b'[', b' ', b']', ..] | [b'[', b'x', b']', ..] | [b'[', b'X', b']', ..] => {
+            Some(indent + 3)
+        }
+        _ => None,
+    }
+}
+
+/// Checklist items (`- [ ] task` or `- [x] task`), every other line is ignored
+fn parse_markdown(line: &str, index: usize) -> Option<Task> {
+    let offset = checkbox_offset(line)?;
+
+    let mut task = Task::new(index);
+    task.done = line.as_bytes()[offset] != b' ';
+    task.read_words(&line[offset + 2..]);
+
+    Some(task)
+}
+
+/// Sets `key:value` in the line, replacing the tag if it is already there (only if it holds a
+/// number, `est:soon` isn't an estimate). Everything else in the line is left alone.
+fn set_tag(line: &str, key: &str, value: u64) -> String {
+    let prefix = format!("{}:", key);
+    let tag = format!("{}{}", prefix, value);
+
+    let mut found = false;
+    let words = line
+        .split(' ')
+        .map(|word| {
+            if word
+                .strip_prefix(&prefix)
+                .is_some_and(|n| n.parse::<u64>().is_ok())
+            {
+                found = true;
+                tag.as_str()
+            } else {
+                word
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    if found {
+        words
+    } else {
+        format!("{} {}", line.trim_end(), tag)
+    }
+}
+
+/// Splits off the line ending (`\n`, `\r\n` or nothing for the last line)
+fn split_line_ending(line: &str) -> (&str, &str) {
+    let content = line.trim_end_matches(['\r', '\n']);
+    (content, &line[content.len()..])
+}
+
+/// Tasks from a todo.txt (or plain text) file or a Markdown checklist. `est:N` / `pomos:N`
+/// anywhere in a task hold the estimated and actual pomodoros.
+///
+/// Changes are written back in the same format, only touching the line of the task that changed.
+pub struct TaskList {
+    path: PathBuf,
+    format: Format,
+    /// Every line of the file including its line ending, so it can be written back exactly
+    lines: Vec<String>,
+    tasks: Vec<Task>,
+    selected: usize,
+}
+
+impl TaskList {
+    /// A file that doesn't exist yet is an empty list
+    pub fn load(path: PathBuf) -> io::Result<TaskList> {
+        let mut list = TaskList {
+            format: Format::from_path(&path),
+            lines: Vec::new(),
+            tasks: Vec::new(),
+            selected: 0,
+            path,
+        };
+        list.set_lines(read_lines(&list.path)?);
+
+        Ok(list)
+    }
+
+    /// Uses `tasks_file` from the config (or `--tasks`), there is no task list if it isn't set.
+    /// It is an error if the file can't be read.
+    pub fn from_config(config: &Config) -> Result<Option<TaskList>, String> {
+        let path = match config.get_string("tasks_file") {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+
+        TaskList::load(PathBuf::from(path))
+            .map(Some)
+            .map_err(|e| format!("{}: {}", path, e))
+    }
+
+    /// Replaces the lines of the file and the tasks read from them, keeping the cursor where it is
+    fn set_lines(&mut self, lines: Vec<String>) {
+        let format = self.format;
+
+        self.tasks = lines
+            .iter()
+            .enumerate()
+            .filter_map(|(i, line)| {
+                let (content, _) = split_line_ending(line);
+
+                match format {
+                    Format::TodoTxt => parse_todo_txt(content, i),
+                    Format::Markdown => parse_markdown(content, i),
+                }
+            })
+            .collect();
+        self.lines = lines;
+        self.selected = self.selected.min(self.tasks.len().saturating_sub(1));
+    }
+
+    pub fn tasks(&self) -> &[Task] {
+        &self.tasks
+    }
+
+    /// Index of the task the cursor is on
+    pub fn selected(&self) -> Option<usize> {
+        if self.tasks.is_empty() {
+            None
+        } else {
+            Some(self.selected)
+        }
+    }
+
+    pub fn selected_task(&self) -> Option<&Task> {
+        self.tasks.get(self.selected)
+    }
+
+    pub fn select_next(&mut self) {
+        if self.selected + 1 < self.tasks.len() {
+            self.selected += 1;
+        }
+    }
+
+    pub fn select_prev(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    /// The task being worked on (the first task that isn't done with the same title as the label)
+    pub fn find_active(&self, label: Option<&str>) -> Option<usize> {
+        let label = label?;
+
+        self.tasks
+            .iter()
+            .position(|task| !task.done && task.title == label)
+    }
+
+    /// Replaces the content of a line in the file, keeping its line ending.
+    ///
+    /// The file is read again first so changes made to other lines since it was loaded are kept. If
+    /// the line itself has changed nothing is written, the tasks are reloaded and an error says so.
+    fn edit_line<F: FnOnce(&str) -> String>(&mut self, index: usize, edit: F) -> io::Result<()> {
+        let mut lines = read_lines(&self.path)?;
+
+        if lines.get(index) != self.lines.get(index) {
+            self.set_lines(lines);
+
+            return Err(io::Error::other(
+                "the file was changed by something else, the tasks have been reloaded",
+            ));
+        }
+
+        let (content, ending) = split_line_ending(&lines[index]);
+        lines[index] = format!("{}{}", edit(content), ending);
+
+        // Write to a temporary file first so a crash mid-write can't leave half of the tasks
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, lines.concat())?;
+        fs::rename(&tmp_path, &self.path)?;
+
+        self.set_lines(lines);
+
+        Ok(())
+    }
+
+    pub fn toggle_done(&mut self) -> io::Result<()> {
+        let task = match self.selected_task() {
+            Some(task) => task,
+            None => return Ok(()),
+        };
+        let (done, line) = (!task.done, task.line);
+
+        match self.format {
+            Format::TodoTxt => self.edit_line(line, |content| {
+                let (indent, rest) = content.split_at(content.len() - content.trim_start().len());
+
+                if done {
+                    format!("{}{}", indent, mark_done(rest, Local::now().date_naive()))
+                } else {
+                    format!("{}{}", indent, mark_undone(rest))
+                }
+            }),
+            Format::Markdown => self.edit_line(line, |content| {
+                let offset = checkbox_offset(content).expect("task lines have a checkbox");
+
+                format!(
+                    "{}{}{}",
+                    &content[..offset],
+                    if done { 'x' } else { ' ' },
+                    &content[offset + 1..]
+                )
+            }),
+        }
+    }
+
+    pub fn set_estimate(&mut self, estimate: u64) -> Result<(), String> {
+        let line = self.selected_task().ok_or("there are no tasks")?.line;
+
+        self.edit_line(line, |content| set_tag(content, "est", estimate))
+            .map_err(|e| format!("couldn't save the tasks: {}", e))
+    }
+
+    /// Adds a pomodoro to the task with the title (if there is one)
+    pub fn add_pomodoro(&mut self, title: &str) -> io::Result<()> {
+        let task = match self.find_active(Some(title)) {
+            Some(index) => &self.tasks[index],
+            None => return Ok(()),
+        };
+        let (pomodoros, line) = (task.pomodoros + 1, task.line);
+
+        self.edit_line(line, |content| set_tag(content, "pomos", pomodoros))
+    }
+}
+
+/// Every line of the file including its line ending, a file that doesn't exist has no lines
+fn read_lines(path: &Path) -> io::Result<Vec<String>> {
+    let s = match fs::read_to_string(path) {
+        Ok(s) => s,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+
+    Ok(s.split_inclusive('\n').map(str::to_string).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    /// A file in a test directory of its own
+    struct TempFile {
+        _dir: TestDir,
+        path: PathBuf,
+    }
+
+    impl TempFile {
+        fn new(name: &str, contents: &str) -> TempFile {
+            let dir = TestDir::new();
+            let path = dir.join(name);
+            fs::write(&path, contents).unwrap();
+
+            TempFile { _dir: dir, path }
+        }
+
+        fn contents(&self) -> String {
+            fs::read_to_string(&self.path).unwrap()
+        }
+    }
+
+    fn titles(tasks: &TaskList) -> Vec<&str> {
+        tasks
+            .tasks()
+            .iter()
+            .map(|task| task.title.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn todo_txt_changes_only_touch_their_line() {
+        let file = TempFile::new(
+            "todo.txt",
+            "(A) 2024-01-01 Write report +work est:2\r\n\
+             \x20 Call back @phone\r\n\
+             # Someday\r\n\
+             x 2024-01-03 2024-01-01 Old task\r\n\
+             Last line est:soon",
+        );
+        let mut tasks = TaskList::load(file.path.clone()).unwrap();
+        assert_eq!(
+            titles(&tasks),
+            [
+                "Write report",
+                "Call back",
+                "Old task",
+                "Last line est:soon"
+            ]
+        );
+        assert_eq!(tasks.tasks()[0].priority, Some('A'));
+        assert_eq!(tasks.tasks()[0].estimate, Some(2));
+
+        tasks.toggle_done().unwrap();
+        tasks.add_pomodoro("Call back").unwrap();
+        tasks.select_next();
+        tasks.select_next();
+        tasks.toggle_done().unwrap();
+        tasks.select_next();
+        tasks.set_estimate(1).unwrap();
+
+        let today = Local::now().date_naive().format("%Y-%m-%d");
+        assert_eq!(
+            file.contents(),
+            format!(
+                "x {} 2024-01-01 Write report +work est:2 pri:A\r\n\
+                 \x20 Call back @phone pomos:1\r\n\
+                 # Someday\r\n\
+                 2024-01-01 Old task\r\n\
+                 Last line est:soon est:1",
+                today
+            )
+        );
+        assert_eq!(tasks.tasks()[0].priority, Some('A'));
+
+        // Loading it again gives the same tasks
+        let reloaded = TaskList::load(file.path.clone()).unwrap();
+        assert_eq!(reloaded.tasks(), tasks.tasks());
+    }
+
+    #[test]
+    fn done_todo_txt_tasks_can_be_undone() {
+        let today = NaiveDate::from_ymd_opt(2024, 1, 5).unwrap();
+
+        for (line, done) in [
+            (
+                "(B) 2024-01-01 Plan +work",
+                "x 2024-01-05 2024-01-01 Plan +work pri:B",
+            ),
+            ("Call back @phone", "x 2024-01-05 Call back @phone"),
+            ("(C) pri:cheap thing", "x 2024-01-05 pri:cheap thing pri:C"),
+        ] {
+            assert_eq!(mark_done(line, today), done);
+            assert_eq!(mark_undone(done), line);
+        }
+
+        // Tasks done elsewhere may not have a completion date
+        assert_eq!(mark_undone("x Old task"), "Old task");
+    }
+
+    #[test]
+    fn markdown_changes_only_touch_their_line() {
+        let file = TempFile::new(
+            "tasks.md",
+            "# Today\r\n\
+             - [ ] Draft the plan est:3\r\n\
+             \x20 * [x] Nested step\r\n\
+             Some notes\r\n\
+             + [ ] Last one\r\n",
+        );
+        let mut tasks = TaskList::load(file.path.clone()).unwrap();
+        assert_eq!(
+            titles(&tasks),
+            ["Draft the plan", "Nested step", "Last one"]
+        );
+        assert!(tasks.tasks()[1].done);
+
+        tasks.toggle_done().unwrap();
+        tasks.select_next();
+        tasks.toggle_done().unwrap();
+        tasks.set_estimate(2).unwrap();
+        tasks.add_pomodoro("Last one").unwrap();
+        tasks.add_pomodoro("Last one").unwrap();
+
+        assert_eq!(
+            file.contents(),
+            "# Today\r\n\
+             - [x] Draft the plan est:3\r\n\
+             \x20 * [ ] Nested step est:2\r\n\
+             Some notes\r\n\
+             + [ ] Last one pomos:2\r\n"
+        );
+    }
+
+    #[test]
+    fn lines_changed_elsewhere_arent_overwritten() {
+        let file = TempFile::new("todo.txt", "First\nSecond\n");
+        let mut tasks = TaskList::load(file.path.clone()).unwrap();
+
+        // Other lines can change, they are kept
+        fs::write(&file.path, "First\nSecond +work\nThird\n").unwrap();
+        tasks.toggle_done().unwrap();
+        let today = Local::now().date_naive().format("%Y-%m-%d");
+        assert_eq!(
+            file.contents(),
+            format!("x {} First\nSecond +work\nThird\n", today)
+        );
+        assert_eq!(titles(&tasks), ["First", "Second", "Third"]);
+
+        // The line being changed can't
+        fs::write(&file.path, "x First\nSecond thing\nThird\n").unwrap();
+        tasks.select_next();
+        assert!(tasks.toggle_done().is_err());
+        assert_eq!(file.contents(), "x First\nSecond thing\nThird\n");
+        assert_eq!(titles(&tasks), ["First", "Second thing", "Third"]);
+    }
+}
@@ -0,0 +1,67 @@
+use crate::config::Config;
+
+use tui::style::Color;
+
+/// Colours used to draw the timer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Theme {
+    pub foreground: Color,
+    pub background: Color,
+    /// Used instead of the foreground once a work session has gone into overtime
+    pub overtime: Color,
+}
+
+impl Theme {
+    /// Uses `theme.foreground`, `theme.background` and `theme.overtime` from the config,
+    /// defaulting to yellow (green in overtime) on red
+    pub fn from_config(config: &Config) -> Theme {
+        let colour = |key: &str, default: Color| {
+            config
+                .get_string(key)
+                .and_then(|value| parse_colour(value))
+                .unwrap_or(default)
+        };
+
+        Theme {
+            foreground: colour("theme.foreground", Color::Yellow),
+            background: colour("theme.background", Color::Red),
+            overtime: colour("theme.overtime", Color::Green),
+        }
+    }
+}
+
+/// Accepts the terminal colour names (`red`, `light_blue`, ...), `reset` for the terminal's
+/// default colour or `#rrggbb`
+pub fn parse_colour(s: &str) -> Option<Color> {
+    if let Some(hex) = s.strip_prefix('#') {
+        if hex.len() != 6 || !hex.is_ascii() {
+            return None;
+        }
+
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        return Some(Color::Rgb(channel(0)?, channel(2)?, channel(4)?));
+    }
+
+    let colour = match s.to_lowercase().replace(['-', ' '], "_").as_str() {
+        "reset" => Color::Reset,
+        "black" => Color::Black,
+        "red" => Color::Red,
+        "green" => Color::Green,
+        "yellow" => Color::Yellow,
+        "blue" => Color::Blue,
+        "magenta" => Color::Magenta,
+        "cyan" => Color::Cyan,
+        "gray" | "grey" => Color::Gray,
+        "dark_gray" | "dark_grey" => Color::DarkGray,
+        "light_red" => Color::LightRed,
+        "light_green" => Color::LightGreen,
+        "light_yellow" => Color::LightYellow,
+        "light_blue" => Color::LightBlue,
+        "light_magenta" => Color::LightMagenta,
+        "light_cyan" => Color::LightCyan,
+        "white" => Color::White,
+        _ => return None,
+    };
+
+    Some(colour)
+}
@@ -1,12 +1,24 @@
 use std::io;
 
+use tui::buffer::Buffer;
 use tui::layout::Rect;
+use tui::style::{Color, Style};
+use tui::widgets::{Text, Widget};
 use tui::Terminal;
 
 use crossterm::{terminal, ExecutableCommand};
 use tui::backend::CrosstermBackend;
 
 use crate::app::App;
+use crate::input::{InputManager, Mode};
+use crate::keymap::{self, Action};
+use crate::palette;
+use crate::tasks::Task;
+
+/// Height of the hook log when it shares the space with the tasks
+const LOG_HEIGHT: u16 = 10;
+/// Lines of output shown for each script in the hook log
+const LOG_OUTPUT_LINES: usize = 3;
 
 pub type BackendType = CrosstermBackend<std::io::Stdout>;
 
@@ -35,7 +47,7 @@ impl Ui {
         })
     }
 
-    pub fn render(&mut self, app: &App) -> Result<(), io::Error> {
+    pub fn render(&mut self, app: &App, input_manager: &InputManager) -> Result<(), io::Error> {
         let size = self.terminal.size()?;
 
         if size != self.last_size {
@@ -45,48 +57,302 @@ impl Ui {
 
         self.terminal.draw(|mut f| {
             use tui::layout::{Constraint, Direction, Layout};
-            use tui::style::{Color, Style};
-            use tui::widgets::{Block, Borders, Gauge, Widget};
+            use tui::style::Modifier;
+            use tui::widgets::{Block, Borders, Gauge, Paragraph, SelectableList};
+
+            // The tasks and the hook log share the space below the timer
+            let (tasks_height, log_height) = match (app.tasks().is_some(), input_manager.show_log())
+            {
+                (true, true) => (Constraint::Min(0), Constraint::Length(LOG_HEIGHT)),
+                (false, true) => (Constraint::Length(0), Constraint::Min(0)),
+                (_, false) => (Constraint::Min(0), Constraint::Length(0)),
+            };
 
             let chunks = Layout::default()
                 .direction(Direction::Vertical)
                 .margin(2)
-                .constraints([Constraint::Length(14), Constraint::Min(0)].as_ref())
+                .constraints(
+                    [
+                        Constraint::Length(14),
+                        // The daily goal
+                        Constraint::Length(if app.goal_progress().is_some() { 3 } else { 0 }),
+                        tasks_height,
+                        log_height,
+                        Constraint::Length(1),
+                    ]
+                    .as_ref(),
+                )
                 .split(size);
 
             let time_left = app.time_left();
+            let theme = app.theme();
 
             let percent_progress = (app.progress() * 100.0).round() as u16;
             let total_work_time = app.total_work_time();
+            let overtime = match app.total_overtime() {
+                (0, 0) => String::new(),
+                (hours, minutes) => format!(" (+{}h{}m overtime)", hours, minutes),
+            };
+            let profile = if app.has_profiles() {
+                format!(" [{}]", app.profile())
+            } else {
+                String::new()
+            };
+            let label = match app.label() {
+                Some(label) => format!(" - {}", label),
+                None => String::new(),
+            };
 
             Gauge::default()
                 .block(
                     Block::default()
                         .title(&format!(
-                            " Timer - {} pomodoros complete - {}h{}m of work - {} ",
+                            " Timer{}{} - {} pomodoros complete - {}h{}m of work{} - {} ",
+                            profile,
+                            label,
                             app.pomodoros(),
                             total_work_time.0,
                             total_work_time.1,
+                            overtime,
                             app.get_state_name()
                         ))
                         .borders(Borders::ALL),
                 )
-                .style(Style::default().fg(Color::Yellow).bg(Color::Red))
+                .style(
+                    Style::default()
+                        .fg(if app.overtime().is_some() {
+                            theme.overtime
+                        } else {
+                            theme.foreground
+                        })
+                        .bg(theme.background),
+                )
                 .percent(percent_progress)
                 .label(&format!(
-                    "-{}:{:02} - {}% {}",
-                    time_left.0,
-                    time_left.1,
+                    "{} - {}% {}",
+                    match app.overtime() {
+                        Some((minutes, seconds)) => format!("+{}:{:02}", minutes, seconds),
+                        None => format!("-{}:{:02}", time_left.0, time_left.1),
+                    },
                     percent_progress,
                     if app.is_paused() { "(Paused)" } else { "" }
                 ))
                 .render(&mut f, chunks[0]);
+
+            if let Some((goal, progress)) = app.goal_progress() {
+                let ratio = goal.ratio(&progress);
+
+                Gauge::default()
+                    .block(Block::default().title(" Today ").borders(Borders::ALL))
+                    .style(Style::default().fg(theme.foreground).bg(theme.background))
+                    .ratio(ratio)
+                    .label(&format!(
+                        "{} - {}%{}",
+                        goal.describe(&progress),
+                        (ratio * 100.0).floor(),
+                        if goal.is_reached(&progress) {
+                            " (goal reached)"
+                        } else {
+                            ""
+                        }
+                    ))
+                    .render(&mut f, chunks[1]);
+            }
+
+            if let Some(tasks) = app.tasks() {
+                let active = tasks.find_active(app.label());
+                let items = tasks
+                    .tasks()
+                    .iter()
+                    .enumerate()
+                    .map(|(i, task)| task_line(task, Some(i) == active))
+                    .collect::<Vec<_>>();
+
+                SelectableList::default()
+                    .block(Block::default().title(" Tasks ").borders(Borders::ALL))
+                    .items(&items)
+                    .select(tasks.selected())
+                    .highlight_style(Style::default().modifier(Modifier::BOLD))
+                    .highlight_symbol(">")
+                    .render(&mut f, chunks[2]);
+            }
+
+            if input_manager.show_log() {
+                // Leave room for the borders
+                let lines = log_lines(input_manager, chunks[3].height.saturating_sub(2));
+
+                Paragraph::new(lines.iter())
+                    .block(Block::default().title(" Hook log ").borders(Borders::ALL))
+                    .render(&mut f, chunks[3]);
+            }
+
+            let status_line = match input_manager.mode() {
+                Mode::Command(line) => Some(format!(":{}", line)),
+                _ => input_manager
+                    .message()
+                    .map(str::to_string)
+                    .or_else(|| failure_notice(input_manager)),
+            };
+
+            if let Some(status_line) = status_line {
+                Paragraph::new([Text::raw(status_line)].iter()).render(&mut f, chunks[4]);
+            }
+
+            if *input_manager.mode() == Mode::Help {
+                let lines = help_lines(input_manager);
+                let width = lines
+                    .iter()
+                    .map(|line| line.chars().count())
+                    .max()
+                    .unwrap_or(0);
+                // Leave room for the borders
+                let area = centered(size, width as u16 + 2, lines.len() as u16 + 2);
+
+                Clear.render(&mut f, area);
+                Paragraph::new([Text::raw(lines.join("\n"))].iter())
+                    .block(Block::default().title(" Help ").borders(Borders::ALL))
+                    .render(&mut f, area);
+            }
         })?;
 
         Ok(())
     }
 }
 
+/// The last `height` lines of the hook log, each finished script followed by the end of its
+/// output. Failures are red.
+fn log_lines(input_manager: &InputManager, height: u16) -> Vec<Text<'static>> {
+    let mut lines = Vec::new();
+
+    for entry in input_manager.hook_log().entries().rev() {
+        if lines.len() >= height as usize {
+            break;
+        }
+
+        let output = entry.output.iter().rev().take(LOG_OUTPUT_LINES);
+        lines.extend(output.map(|line| Text::raw(format!("    {}\n", line))));
+
+        let style = if entry.status.is_failure() {
+            Style::default().fg(Color::Red)
+        } else {
+            Style::default()
+        };
+        lines.push(Text::styled(format!("{}\n", entry.summary()), style));
+    }
+
+    lines.truncate(height as usize);
+    lines.reverse();
+    lines
+}
+
+/// Tells the user about hooks that failed while the log wasn't showing
+fn failure_notice(input_manager: &InputManager) -> Option<String> {
+    let failures = input_manager.hook_log().unseen_failures();
+
+    if failures == 0 {
+        return None;
+    }
+
+    let key = input_manager
+        .keymap()
+        .actions()
+        .into_iter()
+        .find(|(action, _)| *action == Action::HookLog)
+        .and_then(|(_, keys)| keys.first().copied());
+
+    Some(format!(
+        "{} hook{} failed{}",
+        failures,
+        if failures == 1 { "" } else { "s" },
+        match key {
+            Some(key) => format!(", press {} to see the log", keymap::format_key(key)),
+            None => String::new(),
+        }
+    ))
+}
+
+/// `[x]` for tasks that are done, `*` for the one being worked on, the todo.txt priority, projects
+/// and contexts and the pomodoros spent (out of the estimate)
+fn task_line(task: &Task, active: bool) -> String {
+    let priority = match task.priority {
+        Some(priority) => format!("({}) ", priority),
+        None => String::new(),
+    };
+
+    let tags: String = task
+        .projects
+        .iter()
+        .map(|project| format!(" +{}", project))
+        .chain(task.contexts.iter().map(|context| format!(" @{}", context)))
+        .collect();
+
+    let pomodoros = match task.estimate {
+        Some(estimate) => format!(" ({}/{})", task.pomodoros, estimate),
+        None if task.pomodoros > 0 => format!(" ({})", task.pomodoros),
+        None => String::new(),
+    };
+
+    format!(
+        "{} [{}] {}{}{}{}",
+        if active { "*" } else { " " },
+        if task.done { "x" } else { " " },
+        priority,
+        task.title,
+        tags,
+        pomodoros
+    )
+}
+
+/// The bindings from the input manager followed by the palette commands
+fn help_lines(input_manager: &InputManager) -> Vec<String> {
+    let mut lines = vec![" Keys".to_string()];
+
+    for (action, keys) in input_manager.keymap().actions() {
+        let keys = keys
+            .into_iter()
+            .map(keymap::format_key)
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        lines.push(format!("   {:<14} {} ", keys, action.description()));
+    }
+
+    lines.push(String::new());
+    lines.push(" Commands (type : first)".to_string());
+
+    for (usage, description) in palette::USAGE {
+        lines.push(format!("   {:<30} {} ", usage, description));
+    }
+
+    lines
+}
+
+/// A rectangle in the middle of `area`, shrunk to fit if needed
+fn centered(area: Rect, width: u16, height: u16) -> Rect {
+    let width = width.min(area.width);
+    let height = height.min(area.height);
+
+    Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    )
+}
+
+/// Blanks out the area so that whatever is drawn on top doesn't mix with what was underneath
+struct Clear;
+
+impl Widget for Clear {
+    fn draw(&mut self, area: Rect, buf: &mut Buffer) {
+        for y in area.top()..area.bottom() {
+            for x in area.left()..area.right() {
+                buf.get_mut(x, y).reset();
+            }
+        }
+    }
+}
+
 pub fn cleanup() {
     terminal::disable_raw_mode().unwrap();
     io::stdout()
@@ -0,0 +1,546 @@
+use crate::app::App;
+use crate::config::Config;
+use crate::hooks::{Entry, Hook, HookLog, Status, Trigger};
+use crate::status::json_string;
+
+use chrono::{DateTime, Local, SecondsFormat};
+
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Attempts at delivering each payload, unless `webhook_attempts` says otherwise
+const DEFAULT_ATTEMPTS: u64 = 5;
+/// Seconds to wait for a response, unless `webhook_timeout` says otherwise
+const DEFAULT_TIMEOUT: u64 = 10;
+/// The wait before the first retry, it doubles after each one
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// A payload waiting to be delivered
+#[derive(Clone)]
+struct Job {
+    hook: Hook,
+    time: DateTime<Local>,
+    body: String,
+}
+
+/// POSTs a JSON payload to every URL in `webhooks` when a work session or break starts. Each URL
+/// has its own thread that delivers the payloads in order, retrying with a backoff when a request
+/// fails, so nothing waits on the network.
+pub struct Webhooks {
+    workers: Vec<mpsc::Sender<Job>>,
+    log: Arc<Mutex<HookLog>>,
+    /// `webhook_timeout`
+    timeout: Duration,
+}
+
+impl Webhooks {
+    /// There are no webhooks if `webhooks` isn't set. Payloads that can't be delivered end up in
+    /// the hook log.
+    pub fn from_config(config: &Config, log: &Arc<Mutex<HookLog>>) -> Option<Webhooks> {
+        let urls = config
+            .get_list("webhooks")
+            .filter(|urls| !urls.is_empty())?;
+
+        let attempts = config
+            .get_int("webhook_attempts")
+            .unwrap_or(DEFAULT_ATTEMPTS);
+        let timeout =
+            Duration::from_secs(config.get_int("webhook_timeout").unwrap_or(DEFAULT_TIMEOUT));
+        let headers = config
+            .keys_with_prefix("webhook_headers.")
+            .into_iter()
+            .filter_map(|key| {
+                let value = config.get_string(&key)?.clone();
+                Some((key["webhook_headers.".len()..].to_string(), value))
+            })
+            .collect::<Vec<_>>();
+
+        let agent = ureq::AgentBuilder::new().timeout(timeout).build();
+
+        let workers = urls
+            .iter()
+            .map(|url| {
+                let (tx, rx) = mpsc::channel();
+                let worker = Worker {
+                    url: url.clone(),
+                    agent: agent.clone(),
+                    headers: headers.clone(),
+                    attempts,
+                    first_backoff: FIRST_BACKOFF,
+                    log: Arc::clone(log),
+                };
+
+                thread::spawn(move || worker.run(rx));
+                tx
+            })
+            .collect();
+
+        Some(Webhooks {
+            workers,
+            log: Arc::clone(log),
+            timeout,
+        })
+    }
+
+    /// How long each request can take
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Queues the payload for the hooks that start a state, everything else is ignored
+    pub fn handle(&self, trigger: &Trigger, app: &App) {
+        if trigger.hook != Hook::WorkStart && trigger.hook != Hook::BreakStart {
+            return;
+        }
+
+        let time = Local::now();
+
+        self.queue(Job {
+            hook: trigger.hook,
+            time,
+            body: payload(trigger, app.is_paused(), time),
+        });
+    }
+
+    fn queue(&self, job: Job) {
+        for worker in &self.workers {
+            // Counts as being sent until its first attempt is over, so quitting waits for it
+            self.log.lock().unwrap().start_sending();
+
+            if worker.send(job.clone()).is_err() {
+                self.log.lock().unwrap().finish_sending();
+            }
+        }
+    }
+}
+
+/// `{"event":"break_start","state":"short_break","phase":"short_break","previous_state":"work",...}`
+fn payload(trigger: &Trigger, paused: bool, time: DateTime<Local>) -> String {
+    let string = |key| {
+        trigger
+            .var(key)
+            .map(json_string)
+            .unwrap_or_else(|| "null".to_string())
+    };
+
+    let remaining = trigger
+        .var("TOMATO_REMAINING")
+        .and_then(|seconds| seconds.parse().ok())
+        .unwrap_or(0);
+    // There is no end to a state that is paused
+    let ends_at = if paused {
+        "null".to_string()
+    } else {
+        json_string(&timestamp(time + chrono::Duration::seconds(remaining)))
+    };
+
+    format!(
+        "{{\"event\":{},\"state\":{},\"phase\":{},\"previous_state\":{},\"pomodoros\":{},\"task\":{},\"profile\":{},\"timestamp\":{},\"ends_at\":{}}}",
+        json_string(trigger.hook.name()),
+        string("TOMATO_STATE"),
+        string("TOMATO_PHASE"),
+        string("TOMATO_PREV_STATE"),
+        trigger.var("TOMATO_POMODOROS").unwrap_or("0"),
+        string("TOMATO_TASK"),
+        string("TOMATO_PROFILE"),
+        json_string(&timestamp(time)),
+        ends_at
+    )
+}
+
+fn timestamp(time: DateTime<Local>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, false)
+}
+
+/// Delivers the payloads for one URL
+struct Worker {
+    url: String,
+    agent: ureq::Agent,
+    headers: Vec<(String, String)>,
+    attempts: u64,
+    /// The wait before the first retry
+    first_backoff: Duration,
+    log: Arc<Mutex<HookLog>>,
+}
+
+impl Worker {
+    /// Runs until the sender is dropped
+    fn run(self, rx: mpsc::Receiver<Job>) {
+        while let Ok(mut job) = rx.recv() {
+            let mut attempt = 1;
+
+            loop {
+                let result = self.post(&job.body);
+
+                if attempt == 1 {
+                    self.log.lock().unwrap().finish_sending();
+                }
+
+                let (error, retry) = match result {
+                    Ok(()) => break,
+                    Err(error) => error,
+                };
+
+                if !retry || attempt >= self.attempts {
+                    self.log.lock().unwrap().add(Entry {
+                        time: job.time,
+                        hook: job.hook,
+                        status: Status::Undelivered(match attempt {
+                            1 => format!("{}: {}", self.url, error),
+                            _ => format!("{} ({} attempts): {}", self.url, attempt, error),
+                        }),
+                        output: Vec::new(),
+                    });
+                    break;
+                }
+
+                let backoff = self
+                    .first_backoff
+                    .checked_mul(1 << (attempt - 1).min(16))
+                    .map_or(MAX_BACKOFF, |backoff| backoff.min(MAX_BACKOFF));
+
+                match rx.recv_timeout(backoff) {
+                    // The state has changed again since, so this payload is out of date
+                    Ok(newer) => {
+                        job = newer;
+                        attempt = 1;
+                    }
+                    Err(RecvTimeoutError::Timeout) => attempt += 1,
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            }
+        }
+    }
+
+    /// The error and whether it is worth trying again (not for a 4xx response other than 429)
+    fn post(&self, body: &str) -> Result<(), (String, bool)> {
+        let mut request = self
+            .agent
+            .post(&self.url)
+            .set("Content-Type", "application/json");
+
+        for (name, value) in &self.headers {
+            request = request.set(name, value);
+        }
+
+        match request.send_string(body) {
+            Ok(_) => Ok(()),
+            Err(ureq::Error::Status(code, response)) => Err((
+                format!("{} {}", code, response.status_text()),
+                code >= 500 || code == 429,
+            )),
+            // Leaves out the URL, which is already in the log entry
+            Err(ureq::Error::Transport(transport)) => {
+                let mut error = transport.kind().to_string();
+
+                if let Some(message) = transport.message() {
+                    error = format!("{}: {}", error, message);
+                }
+
+                if let Some(source) = std::error::Error::source(&transport) {
+                    error = format!("{}: {}", error, source);
+                }
+
+                Err((error, true))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    use chrono::TimeZone;
+
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::time::Instant;
+
+    /// A request to the stand-in server, the request line and headers are lowercased
+    struct Request {
+        head: String,
+        body: String,
+    }
+
+    /// Answers a request with each of the statuses in turn, then stops listening
+    fn serve(statuses: &[u16]) -> (String, mpsc::Receiver<Request>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let statuses = statuses.to_vec();
+        let (tx, rx) = mpsc::channel();
+
+        thread::spawn(move || {
+            for status in statuses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut head = String::new();
+
+                while !head.ends_with("\r\n\r\n") {
+                    reader.read_line(&mut head).unwrap();
+                }
+
+                let head = head.to_lowercase();
+                let length = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("content-length: "))
+                    .map_or(0, |length| length.parse().unwrap());
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+
+                let reason = match status {
+                    200 => "OK",
+                    404 => "Not Found",
+                    429 => "Too Many Requests",
+                    _ => "Service Unavailable",
+                };
+                write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 {} {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status,
+                    reason
+                )
+                .unwrap();
+
+                let body = String::from_utf8(body).unwrap();
+                if tx.send(Request { head, body }).is_err() {
+                    return;
+                }
+            }
+        });
+
+        (url, rx)
+    }
+
+    /// A hook log (and any config) in a test directory of its own
+    struct Setup {
+        _dir: TestDir,
+        config: Config,
+        log: Arc<Mutex<HookLog>>,
+    }
+
+    impl Setup {
+        fn new(config: &str) -> Setup {
+            let dir = TestDir::new();
+            let config = Config::new_from_config_string(&format!(
+                "hook_log_file={}\n{}",
+                dir.join("hooks.log").display(),
+                config
+            ))
+            .unwrap();
+            let log = Arc::new(Mutex::new(HookLog::from_config(&config)));
+
+            Setup {
+                _dir: dir,
+                config,
+                log,
+            }
+        }
+
+        /// A worker for the URL that retries straight away
+        fn worker(&self, url: &str, attempts: u64) -> mpsc::Sender<Job> {
+            let worker = Worker {
+                url: url.to_string(),
+                agent: ureq::AgentBuilder::new()
+                    .timeout(Duration::from_secs(5))
+                    .build(),
+                headers: Vec::new(),
+                attempts,
+                first_backoff: Duration::from_millis(1),
+                log: Arc::clone(&self.log),
+            };
+            let (tx, rx) = mpsc::channel();
+            thread::spawn(move || worker.run(rx));
+
+            tx
+        }
+
+        fn statuses(&self) -> Vec<Status> {
+            let log = self.log.lock().unwrap();
+            log.entries().map(|entry| entry.status.clone()).collect()
+        }
+
+        /// Waits (for up to five seconds) for the first attempts to be over
+        fn wait_until_sent(&self) {
+            let deadline = Instant::now() + Duration::from_secs(5);
+
+            while self.log.lock().unwrap().sending() > 0 {
+                assert!(Instant::now() < deadline, "the payloads were never sent");
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+    }
+
+    fn job(body: &str) -> Job {
+        Job {
+            hook: Hook::WorkStart,
+            time: Local::now(),
+            body: body.to_string(),
+        }
+    }
+
+    fn recv(requests: &mpsc::Receiver<Request>) -> Request {
+        requests.recv_timeout(Duration::from_secs(5)).unwrap()
+    }
+
+    #[test]
+    fn payloads_describe_the_state_that_started() {
+        let mut trigger = Trigger {
+            hook: Hook::BreakStart,
+            env: vec![
+                ("TOMATO_STATE", "short_break".to_string()),
+                ("TOMATO_PHASE", "coffee".to_string()),
+                ("TOMATO_PREV_STATE", "work".to_string()),
+                ("TOMATO_POMODOROS", "3".to_string()),
+                ("TOMATO_TASK", "write \"report\"".to_string()),
+                ("TOMATO_PROFILE", "default".to_string()),
+                ("TOMATO_REMAINING", "300".to_string()),
+            ],
+        };
+        let time = Local.with_ymd_and_hms(2024, 5, 1, 10, 25, 0).unwrap();
+
+        assert_eq!(
+            payload(&trigger, false, time),
+            format!(
+                "{{\"event\":\"break_start\",\"state\":\"short_break\",\"phase\":\"coffee\",\"previous_state\":\"work\",\"pomodoros\":3,\"task\":\"write \\\"report\\\"\",\"profile\":\"default\",\"timestamp\":\"{}\",\"ends_at\":\"{}\"}}",
+                timestamp(time),
+                timestamp(time + chrono::Duration::minutes(5))
+            )
+        );
+
+        // Without a task, and paused so there is no end
+        trigger.env.retain(|(key, _)| *key != "TOMATO_TASK");
+        let paused = payload(&trigger, true, time);
+        assert!(paused.contains("\"task\":null"));
+        assert!(paused.ends_with("\"ends_at\":null}"));
+    }
+
+    #[test]
+    fn payloads_are_posted_with_the_headers() {
+        let (url, requests) = serve(&[200]);
+        let setup = Setup::new(&format!(
+            "webhooks={}\nwebhook_headers.Authorization=Bearer 1234",
+            url
+        ));
+        let webhooks = Webhooks::from_config(&setup.config, &setup.log).unwrap();
+
+        webhooks.queue(job("{\"event\":\"work_start\"}"));
+        let request = recv(&requests);
+
+        assert!(request.head.starts_with("post /hook http/1.1\r\n"));
+        assert!(request
+            .head
+            .contains("\r\ncontent-type: application/json\r\n"));
+        assert!(request.head.contains("\r\nauthorization: bearer 1234\r\n"));
+        assert_eq!(request.body, "{\"event\":\"work_start\"}");
+
+        setup.wait_until_sent();
+        assert_eq!(setup.statuses(), []);
+    }
+
+    #[test]
+    fn server_errors_are_retried() {
+        let (url, requests) = serve(&[500, 429, 200]);
+        let setup = Setup::new("");
+        let worker = setup.worker(&url, 5);
+
+        setup.log.lock().unwrap().start_sending();
+        worker.send(job("payload")).unwrap();
+
+        for _ in 0..3 {
+            assert_eq!(recv(&requests).body, "payload");
+        }
+
+        setup.wait_until_sent();
+        assert_eq!(setup.statuses(), []);
+    }
+
+    #[test]
+    fn payloads_are_given_up_on() {
+        let (url, requests) = serve(&[404, 503, 503]);
+        let setup = Setup::new("");
+        let worker = setup.worker(&url, 2);
+
+        // Other 4xx responses aren't worth trying again
+        setup.log.lock().unwrap().start_sending();
+        worker.send(job("refused")).unwrap();
+        assert_eq!(recv(&requests).body, "refused");
+
+        // Out of attempts
+        setup.log.lock().unwrap().start_sending();
+        worker.send(job("failed")).unwrap();
+        assert_eq!(recv(&requests).body, "failed");
+        assert_eq!(recv(&requests).body, "failed");
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while setup.statuses().len() < 2 {
+            assert!(Instant::now() < deadline);
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        assert_eq!(
+            setup.statuses(),
+            [
+                Status::Undelivered(format!("{}: 404 Not Found", url)),
+                Status::Undelivered(format!("{} (2 attempts): 503 Service Unavailable", url)),
+            ]
+        );
+        assert_eq!(setup.log.lock().unwrap().sending(), 0);
+    }
+
+    #[test]
+    fn newer_payloads_replace_one_waiting_to_be_retried() {
+        let (url, requests) = serve(&[500, 200]);
+        let setup = Setup::new("");
+        let (tx, rx) = mpsc::channel();
+        let webhooks = Webhooks {
+            workers: vec![tx],
+            log: Arc::clone(&setup.log),
+            timeout: Duration::from_secs(5),
+        };
+
+        // It would be a minute before the old one was tried again
+        let worker = Worker {
+            url: url.clone(),
+            agent: ureq::agent(),
+            headers: Vec::new(),
+            attempts: 5,
+            first_backoff: Duration::from_secs(60),
+            log: Arc::clone(&setup.log),
+        };
+        thread::spawn(move || worker.run(rx));
+
+        webhooks.queue(job("old"));
+        assert_eq!(recv(&requests).body, "old");
+
+        webhooks.queue(job("new"));
+        assert_eq!(recv(&requests).body, "new");
+
+        setup.wait_until_sent();
+        assert_eq!(setup.statuses(), []);
+    }
+
+    #[test]
+    fn payloads_count_as_being_sent_until_their_first_attempt() {
+        let setup = Setup::new("");
+        let (tx, rx) = mpsc::channel();
+        let webhooks = Webhooks {
+            workers: vec![tx],
+            log: Arc::clone(&setup.log),
+            timeout: Duration::from_secs(5),
+        };
+
+        webhooks.queue(job("first"));
+        webhooks.queue(job("second"));
+        assert_eq!(setup.log.lock().unwrap().sending(), 2);
+
+        // Nothing can be sent to a worker that has stopped
+        drop(rx);
+        webhooks.queue(job("third"));
+        assert_eq!(setup.log.lock().unwrap().sending(), 2);
+    }
+}